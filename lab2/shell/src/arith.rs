@@ -0,0 +1,405 @@
+use crate::vars::{is_name, Variables};
+
+/// evaluate an arithmetic expression of `$(( ))`, `(( ))` or `let`, with
+/// 64-bit integers and the operators of C
+///
+/// from high to low precedence: `id++ id--`, `++id --id`, `+ - ! ~` unary,
+/// `**`, `* / %`, `+ -`, `<< >>`, `< <= > >=`, `== !=`, `&`, `^`, `|`, `&&`,
+/// `||`, `?:`, `= *= /= %= += -= <<= >>= &= ^= |=`, `,`. names are variables,
+/// whose values are expressions too, 0 if unset or empty. numbers are decimal,
+/// `0x` hex, `0` octal or `base#digits`
+pub fn evaluate(expr: &str, vars: &mut Variables) -> Result<i64, String> {
+    evaluate_nested(expr, vars, 0)
+}
+
+/// how deep variables may refer to others, like `a=b b=a`
+const MAX_DEPTH: usize = 32;
+
+fn evaluate_nested(expr: &str, vars: &mut Variables, depth: usize) -> Result<i64, String> {
+    let error = |message: String| format!("{}: {}", expr.trim(), message);
+    if depth > MAX_DEPTH {
+        return Err(error("expression recursion level exceeded".to_string()));
+    }
+    let tokens = tokenize(expr).map_err(error)?;
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+    let mut parser = Parser { tokens, pos: 0 };
+    let tree = parser.comma().map_err(error)?;
+    if let Some(token) = parser.tokens.get(parser.pos) {
+        return Err(error(format!(
+            "syntax error in expression (error token is \"{}\")",
+            token
+        )));
+    }
+    tree.eval(vars, depth).map_err(error)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Name(name) => write!(f, "{}", name),
+            Token::Op(op) => write!(f, "{}", op),
+        }
+    }
+}
+
+/// operators, the longer ones first to be matched first
+const OPERATORS: [&str; 37] = [
+    "<<=", ">>=", "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "*=", "/=",
+    "%=", "+=", "-=", "&=", "^=", "|=", "+", "-", "*", "/", "%", "<", ">", "&", "|", "^", "!", "~",
+    "?", ":", "=", ",",
+];
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = expr.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || "_#".contains(chars[i])) {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            tokens.push(match c.is_ascii_digit() {
+                true => Token::Number(parse_number(&word)?),
+                false if is_name(&word) => Token::Name(word),
+                false => return Err(format!("{}: invalid name", word)),
+            });
+        } else if c == '(' || c == ')' {
+            tokens.push(Token::Op(if c == '(' { "(" } else { ")" }));
+            i += 1;
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 3)].iter().collect();
+            match OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+                Some(op) => {
+                    tokens.push(Token::Op(op));
+                    i += op.len();
+                }
+                None => {
+                    let rest: String = chars[i..].iter().collect();
+                    return Err(format!(
+                        "syntax error: invalid arithmetic operator (error token is \"{}\")",
+                        rest
+                    ));
+                }
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// a decimal, `0x` hex, `0` octal or `base#digits` number
+fn parse_number(word: &str) -> Result<i64, String> {
+    let (base, digits) = match word.split_once('#') {
+        Some((base, digits)) => match base.parse::<u32>() {
+            Ok(base @ 2..=36) => (base, digits),
+            _ => return Err(format!("{}: invalid arithmetic base", word)),
+        },
+        None => match word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+            Some(digits) => (16, digits),
+            None if word.len() > 1 && word.starts_with('0') => (8, &word[1..]),
+            None => (10, word),
+        },
+    };
+    // up to 2**63, which wraps to the least value, so that it can be negated
+    let number = u64::from_str_radix(digits, base).map_err(|err| match err.kind() {
+        std::num::IntErrorKind::PosOverflow => format!("{}: integer overflow", word),
+        _ => format!(
+            "{}: value too great for base (error token is \"{}\")",
+            word, word
+        ),
+    })?;
+    match number <= i64::MIN.unsigned_abs() {
+        true => Ok(number as i64),
+        false => Err(format!("{}: integer overflow", word)),
+    }
+}
+
+/// an expression parsed, evaluated once variables are known
+enum Expr {
+    Number(i64),
+    Var(String),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// `name op= value`, op `=` for plain assignment
+    Assign(String, &'static str, Box<Expr>),
+    /// `++name` or `--name`, the change
+    PreIncrement(String, i64),
+    /// `name++` or `name--`, the change
+    PostIncrement(String, i64),
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+/// precedence of binary operators, higher binds tighter
+fn precedence(op: &str) -> Option<u8> {
+    Some(match op {
+        "||" => 1,
+        "&&" => 2,
+        "|" => 3,
+        "^" => 4,
+        "&" => 5,
+        "==" | "!=" => 6,
+        "<" | "<=" | ">" | ">=" => 7,
+        "<<" | ">>" => 8,
+        "+" | "-" => 9,
+        "*" | "/" | "%" => 10,
+        "**" => 11,
+        _ => return None,
+    })
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        match self.peek_op() {
+            Some(next) if next == op => {
+                self.pos += 1;
+                Ok(())
+            }
+            _ => Err(match self.peek() {
+                Some(token) => format!("`{}' expected (error token is \"{}\")", op, token),
+                None => format!("`{}' expected", op),
+            }),
+        }
+    }
+
+    fn comma(&mut self) -> Result<Expr, String> {
+        let mut expr = self.assignment()?;
+        while self.peek_op() == Some(",") {
+            self.pos += 1;
+            expr = Expr::Binary(",", Box::new(expr), Box::new(self.assignment()?));
+        }
+        Ok(expr)
+    }
+
+    fn assignment(&mut self) -> Result<Expr, String> {
+        if let (Some(Token::Name(name)), Some(Token::Op(op))) =
+            (self.peek(), self.tokens.get(self.pos + 1))
+        {
+            if *op == "=" || (op.ends_with('=') && !matches!(*op, "==" | "!=" | "<=" | ">=")) {
+                let (name, op) = (name.clone(), *op);
+                self.pos += 2;
+                return Ok(Expr::Assign(name, op, Box::new(self.assignment()?)));
+            }
+        }
+        self.conditional()
+    }
+
+    fn conditional(&mut self) -> Result<Expr, String> {
+        let condition = self.binary(1)?;
+        if self.peek_op() != Some("?") {
+            return Ok(condition);
+        }
+        self.pos += 1;
+        let then = self.comma()?;
+        self.expect(":")?;
+        let otherwise = self.assignment()?;
+        Ok(Expr::Conditional(
+            Box::new(condition),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
+
+    /// binary operators of at least the precedence, by precedence climbing
+    fn binary(&mut self, min: u8) -> Result<Expr, String> {
+        let mut left = self.unary()?;
+        while let Some((op, prec)) = self
+            .peek_op()
+            .and_then(|op| precedence(op).map(|prec| (op, prec)))
+        {
+            if prec < min {
+                break;
+            }
+            self.pos += 1;
+            // `**` is right associative
+            let next = if op == "**" { prec } else { prec + 1 };
+            let right = self.binary(next)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.peek_op() {
+            Some(op @ ("++" | "--")) => {
+                self.pos += 1;
+                match self.peek().cloned() {
+                    Some(Token::Name(name)) => {
+                        self.pos += 1;
+                        Ok(Expr::PreIncrement(name, if op == "++" { 1 } else { -1 }))
+                    }
+                    // like `--5`, the sign twice
+                    _ => {
+                        let sign = &op[..1];
+                        let operand = Expr::Unary(sign, Box::new(self.unary()?));
+                        Ok(Expr::Unary(sign, Box::new(operand)))
+                    }
+                }
+            }
+            Some(op @ ("+" | "-" | "!" | "~")) => {
+                self.pos += 1;
+                Ok(Expr::Unary(op, Box::new(self.unary()?)))
+            }
+            _ => self.postfix(),
+        }
+    }
+
+    fn postfix(&mut self) -> Result<Expr, String> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        match token {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Name(name)) => match self.peek_op() {
+                Some(op @ ("++" | "--")) => {
+                    self.pos += 1;
+                    Ok(Expr::PostIncrement(name, if op == "++" { 1 } else { -1 }))
+                }
+                _ => Ok(Expr::Var(name)),
+            },
+            Some(Token::Op("(")) => {
+                let expr = self.comma()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Some(token) => Err(format!(
+                "syntax error: operand expected (error token is \"{}\")",
+                token
+            )),
+            None => Err("syntax error: operand expected".to_string()),
+        }
+    }
+}
+
+impl Expr {
+    fn eval(&self, vars: &mut Variables, depth: usize) -> Result<i64, String> {
+        match self {
+            Expr::Number(n) => Ok(*n),
+            Expr::Var(name) => variable(name, vars, depth),
+            Expr::Unary(op, operand) => {
+                let value = operand.eval(vars, depth)?;
+                match *op {
+                    "-" => Ok(value.wrapping_neg()),
+                    "!" => Ok((value == 0) as i64),
+                    "~" => Ok(!value),
+                    _ => Ok(value),
+                }
+            }
+            Expr::Binary("&&", left, right) => {
+                Ok((left.eval(vars, depth)? != 0 && right.eval(vars, depth)? != 0) as i64)
+            }
+            Expr::Binary("||", left, right) => {
+                Ok((left.eval(vars, depth)? != 0 || right.eval(vars, depth)? != 0) as i64)
+            }
+            Expr::Binary(op, left, right) => {
+                let left = left.eval(vars, depth)?;
+                let right = right.eval(vars, depth)?;
+                apply(op, left, right)
+            }
+            Expr::Conditional(condition, then, otherwise) => {
+                match condition.eval(vars, depth)? != 0 {
+                    true => then.eval(vars, depth),
+                    false => otherwise.eval(vars, depth),
+                }
+            }
+            Expr::Assign(name, op, value) => {
+                let value = value.eval(vars, depth)?;
+                let value = match *op {
+                    "=" => value,
+                    _ => apply(&op[..op.len() - 1], variable(name, vars, depth)?, value)?,
+                };
+                assign(name, value, vars)
+            }
+            Expr::PreIncrement(name, change) => {
+                let value = variable(name, vars, depth)?
+                    .checked_add(*change)
+                    .ok_or_else(overflow)?;
+                assign(name, value, vars)
+            }
+            Expr::PostIncrement(name, change) => {
+                let value = variable(name, vars, depth)?;
+                assign(name, value.checked_add(*change).ok_or_else(overflow)?, vars)?;
+                Ok(value)
+            }
+        }
+    }
+}
+
+fn overflow() -> String {
+    "integer overflow".to_string()
+}
+
+/// the value of a variable, evaluated as an expression
+fn variable(name: &str, vars: &mut Variables, depth: usize) -> Result<i64, String> {
+    let value = vars.get(name).unwrap_or_default().to_string();
+    match value.trim().parse() {
+        Ok(n) => Ok(n),
+        Err(_) => evaluate_nested(&value, vars, depth + 1),
+    }
+}
+
+fn assign(name: &str, value: i64, vars: &mut Variables) -> Result<i64, String> {
+    vars.set(name, &value.to_string())?;
+    Ok(value)
+}
+
+fn apply(op: &str, left: i64, right: i64) -> Result<i64, String> {
+    let division = |f: fn(i64, i64) -> Option<i64>| match right {
+        0 => Err("division by 0".to_string()),
+        _ => f(left, right).ok_or_else(overflow),
+    };
+    match op {
+        "+" => left.checked_add(right).ok_or_else(overflow),
+        "-" => left.checked_sub(right).ok_or_else(overflow),
+        "*" => left.checked_mul(right).ok_or_else(overflow),
+        "/" => division(i64::checked_div),
+        "%" => division(i64::checked_rem),
+        "**" => match u32::try_from(right) {
+            Ok(exponent) => left.checked_pow(exponent).ok_or_else(overflow),
+            Err(_) if right < 0 => Err("exponent less than 0".to_string()),
+            Err(_) => Err(overflow()),
+        },
+        // the count wraps like in C, as bash does
+        "<<" => Ok(left.wrapping_shl(right as u32)),
+        ">>" => Ok(left.wrapping_shr(right as u32)),
+        "<" => Ok((left < right) as i64),
+        "<=" => Ok((left <= right) as i64),
+        ">" => Ok((left > right) as i64),
+        ">=" => Ok((left >= right) as i64),
+        "==" => Ok((left == right) as i64),
+        "!=" => Ok((left != right) as i64),
+        "&" => Ok(left & right),
+        "^" => Ok(left ^ right),
+        "|" => Ok(left | right),
+        "," => Ok(right),
+        _ => unreachable!(),
+    }
+}
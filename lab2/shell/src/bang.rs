@@ -0,0 +1,384 @@
+use crate::history::History;
+
+/// a command line after history expansion
+pub struct Expansion {
+    pub line: String,
+    /// the `:p` modifier was used, print the line without running it
+    pub print_only: bool,
+}
+
+/// expand history references in a line, like csh and bash
+///
+/// events: `!!`, `!n`, `!-n`, `!prefix`, `!?text?`, `!#` for the line so far;
+/// words: `:n`, `:x-y`, `:x-`, `:x*`, `^`, `$`, `*`, the `:` omitted before
+/// the last three; modifiers: `:h`, `:t`, `:r`, `:e`, `:p`, `:q`, `:s/old/new/`,
+/// `:gs/old/new/`, `:&`; and `^old^new^` at the start of the line
+/// return None if nothing is expanded, an error if an event is not found
+pub fn expand(line: &str, history: &History) -> Result<Option<Expansion>, String> {
+    let quick;
+    let line = match line.strip_prefix('^') {
+        // `^old^new^` is `!!:s^old^new^`
+        Some(_) => {
+            quick = format!("!!:s{}", line);
+            &quick
+        }
+        None => line,
+    };
+    let mut expander = Expander {
+        chars: line.chars().collect(),
+        history,
+        output: String::new(),
+        print_only: false,
+        last_substitution: None,
+    };
+    let changed = expander.run()?;
+    Ok(changed.then_some(Expansion {
+        line: expander.output,
+        print_only: expander.print_only,
+    }))
+}
+
+struct Expander<'a> {
+    chars: Vec<char>,
+    history: &'a History,
+    /// the line expanded so far
+    output: String,
+    print_only: bool,
+    /// (old, new) of the last `:s`, repeated by `:&`
+    last_substitution: Option<(String, String)>,
+}
+
+impl Expander<'_> {
+    /// expand the whole line to `output`, return whether anything was expanded
+    fn run(&mut self) -> Result<bool, String> {
+        let mut changed = false;
+        let mut quote = None;
+        let mut i = 0;
+        while i < self.chars.len() {
+            let c = self.chars[i];
+            match (quote, c) {
+                // nothing is expanded in single quotes
+                (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+                (None, '\'' | '"') => quote = Some(c),
+                (Some('\''), _) => (),
+                (_, '\\') if i + 1 < self.chars.len() => {
+                    self.output.push(c);
+                    i += 1;
+                }
+                (_, '!') if self.starts_reference(i) => {
+                    i = self.reference(i)?;
+                    changed = true;
+                    continue;
+                }
+                _ => (),
+            }
+            self.output.push(self.chars[i]);
+            i += 1;
+        }
+        Ok(changed)
+    }
+
+    /// whether the `!` at `i` starts a history reference, not like `!=`, `$!`,
+    /// `${!name}` or `! command`
+    fn starts_reference(&self, i: usize) -> bool {
+        let after = match self.chars.get(i + 1) {
+            Some(&c) => c,
+            None => return false,
+        };
+        let before = i.checked_sub(1).map(|i| self.chars[i]);
+        !after.is_whitespace()
+            && !matches!(after, '=' | '(' | '"')
+            && !matches!(before, Some('$' | '{'))
+    }
+
+    /// expand the reference at `i`, return the index after it
+    fn reference(&mut self, i: usize) -> Result<usize, String> {
+        let (event, mut j) = self.event(i + 1)?;
+        let mut text = event.clone();
+        // word designators
+        let designator = match self.chars.get(j) {
+            Some(':')
+                if matches!(
+                    self.chars.get(j + 1),
+                    Some('0'..='9' | '^' | '$' | '*' | '-')
+                ) =>
+            {
+                Some(j + 1)
+            }
+            Some('^' | '$' | '*') => Some(j),
+            _ => None,
+        };
+        if let Some(designator) = designator {
+            let (words, end) = self.words(&event, designator)?;
+            text = words;
+            j = end;
+        }
+        // modifiers
+        while self.chars.get(j) == Some(&':') {
+            j = self.modifier(&mut text, j + 1)?;
+        }
+        self.output += &text;
+        Ok(j)
+    }
+
+    /// the event designated at `i`, and the index after it
+    fn event(&self, i: usize) -> Result<(String, usize), String> {
+        let not_found = |text: &str| format!("!{}: event not found", text);
+        let chars = &self.chars;
+        match chars[i] {
+            '!' => Ok((
+                self.history.last().ok_or_else(|| not_found("!"))?.clone(),
+                i + 1,
+            )),
+            '#' => Ok((self.output.clone(), i + 1)),
+            // `!$` and such refer to the last command
+            '^' | '$' | '*' | ':' => {
+                Ok((self.history.last().ok_or_else(|| not_found(""))?.clone(), i))
+            }
+            '?' => {
+                let end = (i + 1..chars.len())
+                    .find(|&j| chars[j] == '?' || chars[j] == '\n')
+                    .unwrap_or(chars.len());
+                let text: String = chars[i + 1..end].iter().collect();
+                let command = (0..self.history.size())
+                    .filter_map(|index| self.history.rget(index))
+                    .find(|command| command.contains(&text))
+                    .ok_or_else(|| not_found(&format!("?{}", text)))?;
+                let end = if chars.get(end) == Some(&'?') {
+                    end + 1
+                } else {
+                    end
+                };
+                Ok((command.clone(), end))
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                let start = if c == '-' { i + 1 } else { i };
+                let end = (start..chars.len())
+                    .find(|&j| !chars[j].is_ascii_digit())
+                    .unwrap_or(chars.len());
+                let text: String = chars[i..end].iter().collect();
+                let command = match text.parse::<i64>() {
+                    Ok(n) if n < 0 => self.history.rget((-n - 1) as usize),
+                    Ok(n) => self.history.get(n as usize),
+                    Err(_) => None,
+                };
+                Ok((command.ok_or_else(|| not_found(&text))?.clone(), end))
+            }
+            _ => {
+                let end = (i..chars.len())
+                    .find(|&j| chars[j].is_whitespace() || ":;&|<>()'\"".contains(chars[j]))
+                    .unwrap_or(chars.len());
+                let prefix: String = chars[i..end].iter().collect();
+                let command = (0..self.history.size())
+                    .filter_map(|index| self.history.rget(index))
+                    .find(|command| command.starts_with(&prefix))
+                    .ok_or_else(|| not_found(&prefix))?;
+                Ok((command.clone(), end))
+            }
+        }
+    }
+
+    /// the words of an event selected by the designator at `i`, and the index
+    /// after the designator
+    fn words(&self, event: &str, i: usize) -> Result<(String, usize), String> {
+        let words = split_words(event);
+        let last = words.len().saturating_sub(1);
+        let chars = &self.chars;
+        let number = |j: usize| {
+            let end = (j..chars.len())
+                .find(|&k| !chars[k].is_ascii_digit())
+                .unwrap_or(chars.len());
+            let text: String = chars[j..end].iter().collect();
+            (text.parse::<usize>().ok(), end)
+        };
+        let (start, mut j) = match chars[i] {
+            '^' => (1, i + 1),
+            '$' => (last, i + 1),
+            '*' => return Ok((words.get(1..).unwrap_or_default().join(" "), i + 1)),
+            '-' => (0, i),
+            _ => match number(i) {
+                (Some(n), end) => (n, end),
+                (None, _) => return Err("bad word specifier".to_string()),
+            },
+        };
+        let end = match chars.get(j) {
+            // `x*` is empty when there are no words from x on
+            Some('*') if start > last => return Ok((String::new(), j + 1)),
+            Some('*') => {
+                j += 1;
+                last
+            }
+            Some('-') => match chars.get(j + 1) {
+                Some('$') => {
+                    j += 2;
+                    last
+                }
+                Some(c) if c.is_ascii_digit() => {
+                    let (n, end) = number(j + 1);
+                    j = end;
+                    n.unwrap_or(last)
+                }
+                // `x-` leaves the last word out
+                _ => {
+                    j += 1;
+                    last.saturating_sub(1)
+                }
+            },
+            _ => start,
+        };
+        if start > last || end > last || start > end {
+            return Err("bad word specifier".to_string());
+        }
+        Ok((words.get(start..=end).unwrap_or_default().join(" "), j))
+    }
+
+    /// apply the modifier at `i` to the text, return the index after it
+    fn modifier(&mut self, text: &mut String, i: usize) -> Result<usize, String> {
+        let bad_modifier = || {
+            format!(
+                "{}: bad modifier",
+                self.chars.get(i).copied().unwrap_or(' ')
+            )
+        };
+        match self.chars.get(i) {
+            Some('h') => {
+                if let Some(index) = text.rfind('/') {
+                    text.truncate(index.max(1));
+                }
+            }
+            Some('t') => {
+                if let Some(index) = text.rfind('/') {
+                    *text = text[index + 1..].to_string();
+                }
+            }
+            Some('r') => {
+                if let Some(index) = text.rfind('.').filter(|&dot| !text[dot..].contains('/')) {
+                    text.truncate(index);
+                }
+            }
+            Some('e') => {
+                *text = match text.rfind('.').filter(|&dot| !text[dot..].contains('/')) {
+                    Some(index) => text[index..].to_string(),
+                    None => String::new(),
+                };
+            }
+            Some('p') => self.print_only = true,
+            Some('q') => *text = format!("'{}'", text.replace('\'', "'\\''")),
+            Some('s') => return self.substitute(text, i + 1, false),
+            Some('g') => match self.chars.get(i + 1) {
+                Some('s') => return self.substitute(text, i + 2, true),
+                Some('&') => {
+                    self.repeat_substitution(text, true)?;
+                    return Ok(i + 2);
+                }
+                _ => return Err(bad_modifier()),
+            },
+            Some('&') => self.repeat_substitution(text, false)?,
+            _ => return Err(bad_modifier()),
+        }
+        Ok(i + 1)
+    }
+
+    /// `s/old/new/` with the delimiter at `i`, `&` in new stands for old
+    /// the last delimiter may be left out at the end of the line
+    fn substitute(&mut self, text: &mut String, i: usize, global: bool) -> Result<usize, String> {
+        let delimiter = *self.chars.get(i).ok_or("no previous substitution")?;
+        let (old, j) = self.delimited(i + 1, delimiter);
+        let (new, j) = self.delimited(j, delimiter);
+        let old = match old.is_empty() {
+            true => match &self.last_substitution {
+                Some((old, _)) => old.clone(),
+                None => return Err("no previous substitution".to_string()),
+            },
+            false => old,
+        };
+        let new = new.replace('&', &old);
+        self.last_substitution = Some((old, new));
+        self.repeat_substitution(text, global)?;
+        Ok(j)
+    }
+
+    /// text up to the delimiter, or to the end of the line, and the index after
+    /// it. a backslash escapes the delimiter
+    fn delimited(&self, mut i: usize, delimiter: char) -> (String, usize) {
+        let mut text = String::new();
+        while let Some(&c) = self.chars.get(i) {
+            i += 1;
+            match c {
+                _ if c == delimiter => return (text, i),
+                '\n' => return (text, i - 1),
+                '\\' if self.chars.get(i) == Some(&delimiter) => {
+                    text.push(delimiter);
+                    i += 1;
+                }
+                _ => text.push(c),
+            }
+        }
+        (text, i)
+    }
+
+    fn repeat_substitution(&self, text: &mut String, global: bool) -> Result<(), String> {
+        let (old, new) = self
+            .last_substitution
+            .as_ref()
+            .ok_or("no previous substitution")?;
+        if !text.contains(old.as_str()) {
+            return Err(format!("{}: substitution failed", old));
+        }
+        *text = match global {
+            true => text.replace(old.as_str(), new),
+            false => text.replacen(old.as_str(), new, 1),
+        };
+        Ok(())
+    }
+}
+
+/// the words of a command line as history references count them: quoted
+/// parts stay in their words, and operators like `|` are words of their own
+fn split_words(text: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quote = None;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), _) if c == q => {
+                quote = None;
+                word.push(c);
+            }
+            (Some(_), _) => word.push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.push(c);
+            }
+            (None, '\\') => {
+                word.push(c);
+                word.extend(chars.next());
+            }
+            (None, _) if c.is_whitespace() => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            (None, _) if "|&;<>()".contains(c) => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+                let mut operator = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    if !"|&;<>".contains(next) {
+                        break;
+                    }
+                    operator.push(next);
+                    chars.next();
+                }
+                words.push(operator);
+            }
+            (None, _) => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
@@ -0,0 +1,1356 @@
+use crate::arith;
+use crate::complete::Completer;
+use crate::condition;
+use crate::dirs;
+use crate::exec::{exit_shell, run_file, wait_foreground};
+use crate::jobs::JobState;
+use crate::prompt;
+use crate::redirect::FdTable;
+use crate::resolve::{self, Kind};
+use crate::shell::{Control, Options, Shell};
+use crate::signals::{self, Disposition};
+use crate::vars::{is_name, quote, split_assignment, Variable, Variables};
+use nix::errno::Errno;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::cmp::min;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// names of built-in commands, run by the shell itself
+pub const BUILT_INS: [&str; 31] = [
+    "history", "cd", "pushd", "popd", "dirs", "export", "exit", "jobs", "fg", "bg", "kill", "trap",
+    "shopt", "complete", "unset", "readonly", "set", "alias", "unalias", "local", "let", "return",
+    "break", "continue", "test", "[", "source", ".", "type", "command", "hash",
+];
+
+pub fn is_built_in(prog: &str) -> bool {
+    BUILT_INS.contains(&prog)
+}
+
+/// whether a built-in runs other commands, which need the fds of the shell
+/// itself set up like those of the built-in
+pub fn runs_commands(prog: &str) -> bool {
+    matches!(prog, "source" | ".")
+}
+
+/// where a built-in writes its output and errors, following the fds of the command
+pub struct Output {
+    pub out: Box<dyn Write>,
+    pub err: Box<dyn Write>,
+}
+
+impl Output {
+    pub fn new(fds: &FdTable) -> Output {
+        Output {
+            out: fds.writer(1),
+            err: fds.writer(2),
+        }
+    }
+}
+
+/// built-in commands, return the exit status
+pub fn do_built_in(prog: &str, args: &[String], shell: &mut Shell, output: &mut Output) -> i32 {
+    let result = match prog {
+        "history" => history(args, shell, output),
+        "cd" => cd(args, shell, output),
+        "pushd" => pushd(args, shell, output),
+        "popd" => popd(args, shell, output),
+        "dirs" => dirs_built_in(args, shell, output),
+        "export" => export(args, shell, output),
+        "unset" => unset(args, shell, output),
+        "readonly" => readonly(args, shell, output),
+        "set" => set(args, shell, output),
+        "alias" => alias(args, shell, output),
+        "unalias" => unalias(args, shell, output),
+        "local" => local(args, shell, output),
+        "let" => let_built_in(args, shell, output),
+        "return" => return_built_in(args, shell, output),
+        "source" | "." => source(prog, args, shell, output),
+        "break" => break_continue(prog, args, shell, output),
+        "continue" => break_continue(prog, args, shell, output),
+        "test" => test(prog, args, output),
+        "[" => test(prog, args, output),
+        "exit" => exit_built_in(args, shell, output),
+        "jobs" => jobs(shell, output),
+        "fg" => fg(args, shell, output),
+        "bg" => bg(args, shell, output),
+        "kill" => kill_built_in(args, shell, output),
+        "trap" => trap(args, shell, output),
+        "shopt" => shopt(args, shell, output),
+        "complete" => complete(args, shell, output),
+        "type" => type_built_in(args, shell, output),
+        "command" => command(args, shell, output),
+        "hash" => hash(args, shell, output),
+        _ => Ok(0),
+    };
+    output.out.flush().ok();
+    result.unwrap_or_else(|err| {
+        writeln!(output.err, "{}: write error: {}", prog, strerror(&err)).ok();
+        1
+    })
+}
+
+/// `history [-v] [N]` lists the last N commands, all of them by default, and
+/// with `-v` when, where and with what status they ran
+/// `-c` clears the list, `-d N` deletes a command, `-s pattern` lists the
+/// commands containing the pattern, `-w`/`-r [file]` write/read the list to/from
+/// a file, the history file by default
+fn history(args: &[String], shell: &mut Shell, output: &mut Output) -> io::Result<i32> {
+    let history = &mut shell.history;
+    let arg = args.get(1).map(String::as_str);
+    let (verbose, args) = match args.first().map(String::as_str) {
+        Some("-c") => {
+            history.clear();
+            return Ok(0);
+        }
+        Some("-d") => {
+            let removed = arg
+                .and_then(|arg| arg.parse::<usize>().ok())
+                .and_then(|num| history.remove(num));
+            if removed.is_none() {
+                writeln!(
+                    output.err,
+                    "history: {}: history position out of range",
+                    arg.unwrap_or_default()
+                )?;
+                return Ok(1);
+            }
+            return Ok(0);
+        }
+        Some(option @ ("-w" | "-r")) => {
+            let result = match option {
+                "-w" => history.write(arg),
+                _ => history.read(arg),
+            };
+            if let Err(err) = result {
+                let file = arg.unwrap_or("history file");
+                writeln!(output.err, "history: {}: {}", file, strerror(&err))?;
+                return Ok(1);
+            }
+            return Ok(0);
+        }
+        Some("-s") => {
+            let pattern = match arg {
+                Some(pattern) => pattern,
+                None => {
+                    writeln!(output.err, "history: -s: option requires an argument")?;
+                    return Ok(2);
+                }
+            };
+            for num in 1..=history.size() {
+                let command = history.get(num).unwrap();
+                if command.contains(pattern) {
+                    writeln!(output.out, "{:5}  {}", num, command)?;
+                }
+            }
+            return Ok(0);
+        }
+        Some("-v") => (true, &args[1..]),
+        _ => (false, args),
+    };
+    let number = match args.first().map(|arg| arg.parse::<usize>()) {
+        Some(Ok(number)) => number,
+        Some(Err(_)) => {
+            writeln!(
+                output.err,
+                "history: {}: numeric argument required",
+                args[0]
+            )?;
+            return Ok(2);
+        }
+        None => history.size(),
+    };
+    let history_size = history.size();
+    for num in history_size - min(number, history_size) + 1..=history_size {
+        let entry = history.entry(num).unwrap();
+        if !verbose {
+            writeln!(output.out, "{:5}  {}", num, entry.command)?;
+            continue;
+        }
+        let time = match entry.time {
+            Some(time) => {
+                let tm = prompt::local_time(time);
+                format!(
+                    "{}-{:02}-{:02} {:02}:{:02}:{:02}",
+                    tm.tm_year + 1900,
+                    tm.tm_mon + 1,
+                    tm.tm_mday,
+                    tm.tm_hour,
+                    tm.tm_min,
+                    tm.tm_sec
+                )
+            }
+            None => "-".repeat(19),
+        };
+        let status = entry
+            .status
+            .map_or("-".to_string(), |status| status.to_string());
+        let cwd = entry.cwd.as_deref().unwrap_or("-");
+        writeln!(
+            output.out,
+            "{:5}  {}  {:>3}  {}  {}",
+            num, time, status, cwd, entry.command
+        )?;
+    }
+    Ok(0)
+}
+
+/// `cd [-L|-P] [dir]` goes to dir, the home directory by default, or `$OLDPWD`
+/// for `-`. a relative dir is searched in `$CDPATH`. `-P` resolves symbolic
+/// links in `$PWD`, and `-L` by default keeps them, so that `..` goes back
+fn cd(args: &[String], shell: &mut Shell, output: &mut Output) -> io::Result<i32> {
+    let mut physical = false;
+    let mut args = args;
+    while let Some(option) = args
+        .first()
+        .filter(|arg| arg.starts_with('-') && arg.len() > 1)
+    {
+        match option.as_str() {
+            "-L" => physical = false,
+            "-P" => physical = true,
+            "--" => {
+                args = &args[1..];
+                break;
+            }
+            _ => {
+                writeln!(output.err, "cd: {}: invalid option", option)?;
+                writeln!(output.err, "cd: usage: cd [-L|-P] [dir]")?;
+                return Ok(2);
+            }
+        }
+        args = &args[1..];
+    }
+    if args.len() > 1 {
+        writeln!(output.err, "cd: too many arguments")?;
+        return Ok(1);
+    }
+    let name = match args.first() {
+        None => "HOME",
+        Some(arg) if arg == "-" => "OLDPWD",
+        Some(_) => "",
+    };
+    let dir = match args.first() {
+        Some(dir) if name.is_empty() => dir.clone(),
+        _ => match shell.vars.get(name) {
+            Some(dir) => dir.to_string(),
+            None => {
+                writeln!(output.err, "cd: {} not set", name)?;
+                return Ok(1);
+            }
+        },
+    };
+    let (path, searched) = dirs::resolve(&dir, shell);
+    if let Err(err) = dirs::change(&path, physical, shell) {
+        writeln!(output.err, "cd: {}: {}", dir, strerror(&err))?;
+        return Ok(1);
+    }
+    // where it went is not plain to see from the argument
+    if searched || name == "OLDPWD" {
+        writeln!(output.out, "{}", dirs::current(shell))?;
+    }
+    Ok(0)
+}
+
+/// `pushd [-n] [dir]` goes to dir and puts the directory left on the stack,
+/// `-n` only puts dir under the top of the stack. `pushd +N` or `-N` brings the
+/// Nth entry of the stack to the top by rotating it, counted from the left with
+/// `+` and from the right with `-`, and `pushd` alone swaps the top two
+fn pushd(args: &[String], shell: &mut Shell, output: &mut Output) -> io::Result<i32> {
+    let (change, args) = match args.first().map(String::as_str) {
+        Some("-n") => (false, &args[1..]),
+        _ => (true, args),
+    };
+    let mut list = dir_list(shell);
+    let index = match args.first() {
+        Some(arg) if is_stack_index(arg) => match stack_index(arg, list.len()) {
+            Some(index) => index,
+            None => {
+                writeln!(
+                    output.err,
+                    "pushd: {}: directory stack index out of range",
+                    arg
+                )?;
+                return Ok(1);
+            }
+        },
+        Some(dir) => {
+            let (path, _) = dirs::resolve(dir, shell);
+            if !change {
+                shell.dir_stack.insert(0, dirs::normalize(&path));
+                return print_dirs(shell, output, false);
+            }
+            if let Err(err) = dirs::change(&path, false, shell) {
+                writeln!(output.err, "pushd: {}: {}", dir, strerror(&err))?;
+                return Ok(1);
+            }
+            shell.dir_stack.insert(0, list.swap_remove(0));
+            return print_dirs(shell, output, false);
+        }
+        None if list.len() < 2 => {
+            writeln!(output.err, "pushd: no other directory")?;
+            return Ok(1);
+        }
+        None => {
+            list.swap(0, 1);
+            0
+        }
+    };
+    list.rotate_left(index);
+    if let Err(err) = dirs::change(&list[0], false, shell) {
+        writeln!(output.err, "pushd: {}: {}", list[0], strerror(&err))?;
+        return Ok(1);
+    }
+    shell.dir_stack = list.split_off(1);
+    print_dirs(shell, output, false)
+}
+
+/// `popd [+N | -N]` takes the top of the directory stack off and goes to the
+/// next one, or takes off the Nth entry, counted as with `pushd`
+fn popd(args: &[String], shell: &mut Shell, output: &mut Output) -> io::Result<i32> {
+    let list = dir_list(shell);
+    let index = match args.first() {
+        Some(arg) if is_stack_index(arg) => match stack_index(arg, list.len()) {
+            Some(index) => index,
+            None => {
+                writeln!(
+                    output.err,
+                    "popd: {}: directory stack index out of range",
+                    arg
+                )?;
+                return Ok(1);
+            }
+        },
+        Some(arg) => {
+            writeln!(output.err, "popd: {}: invalid argument", arg)?;
+            writeln!(output.err, "popd: usage: popd [+N | -N]")?;
+            return Ok(2);
+        }
+        None => 0,
+    };
+    if list.len() < 2 {
+        writeln!(output.err, "popd: directory stack empty")?;
+        return Ok(1);
+    }
+    if index > 0 {
+        shell.dir_stack.remove(index - 1);
+        return print_dirs(shell, output, false);
+    }
+    if let Err(err) = dirs::change(&list[1], false, shell) {
+        writeln!(output.err, "popd: {}: {}", list[1], strerror(&err))?;
+        return Ok(1);
+    }
+    shell.dir_stack.remove(0);
+    print_dirs(shell, output, false)
+}
+
+/// `dirs [-clpv] [+N | -N]` shows the directory stack, the current directory
+/// first. `-c` clears it, `-l` shows the home directory in full rather than as
+/// `~`, `-p` one entry per line, `-v` one per line with its number, and `+N` or
+/// `-N` only the Nth entry
+fn dirs_built_in(args: &[String], shell: &mut Shell, output: &mut Output) -> io::Result<i32> {
+    let (mut long, mut lines, mut numbered) = (false, false, false);
+    let mut index = None;
+    for arg in args {
+        if is_stack_index(arg) {
+            match stack_index(arg, shell.dir_stack.len() + 1) {
+                Some(n) => index = Some(n),
+                None => {
+                    writeln!(
+                        output.err,
+                        "dirs: {}: directory stack index out of range",
+                        arg
+                    )?;
+                    return Ok(1);
+                }
+            }
+            continue;
+        }
+        let flags = match arg.strip_prefix('-') {
+            Some(flags) if !flags.is_empty() => flags,
+            _ => {
+                writeln!(output.err, "dirs: {}: invalid argument", arg)?;
+                writeln!(output.err, "dirs: usage: dirs [-clpv] [+N] [-N]")?;
+                return Ok(2);
+            }
+        };
+        for flag in flags.chars() {
+            match flag {
+                'c' => shell.dir_stack.clear(),
+                'l' => long = true,
+                'p' => lines = true,
+                'v' => numbered = true,
+                _ => {
+                    writeln!(output.err, "dirs: -{}: invalid option", flag)?;
+                    writeln!(output.err, "dirs: usage: dirs [-clpv] [+N] [-N]")?;
+                    return Ok(2);
+                }
+            }
+        }
+        if flags.contains('c') {
+            return Ok(0);
+        }
+    }
+    let show = |dir: &String| match long {
+        true => dir.clone(),
+        false => dirs::abbreviate(dir, shell),
+    };
+    let list = dir_list(shell);
+    if let Some(index) = index {
+        writeln!(output.out, "{}", show(&list[index]))?;
+        return Ok(0);
+    }
+    match (numbered, lines) {
+        (true, _) => {
+            for (n, dir) in list.iter().enumerate() {
+                writeln!(output.out, "{:2}  {}", n, show(dir))?;
+            }
+        }
+        (false, true) => {
+            for dir in &list {
+                writeln!(output.out, "{}", show(dir))?;
+            }
+        }
+        (false, false) => print_dirs(shell, output, long).map(|_| ())?,
+    }
+    Ok(0)
+}
+
+/// the current directory, then those on the stack
+fn dir_list(shell: &Shell) -> Vec<String> {
+    let mut list = vec![dirs::current(shell)];
+    list.extend(shell.dir_stack.iter().cloned());
+    list
+}
+
+/// print the directory stack on one line, like `dirs`
+fn print_dirs(shell: &Shell, output: &mut Output, long: bool) -> io::Result<i32> {
+    let list: Vec<String> = dir_list(shell)
+        .iter()
+        .map(|dir| match long {
+            true => dir.clone(),
+            false => dirs::abbreviate(dir, shell),
+        })
+        .collect();
+    writeln!(output.out, "{}", list.join(" "))?;
+    Ok(0)
+}
+
+/// whether an argument is `+N` or `-N`
+fn is_stack_index(arg: &str) -> bool {
+    arg.len() > 1 && arg.starts_with(['+', '-']) && arg[1..].chars().all(|c| c.is_ascii_digit())
+}
+
+/// the position in a directory stack of `count` entries named by `+N` or `-N`
+fn stack_index(arg: &str, count: usize) -> Option<usize> {
+    let n: usize = arg[1..].parse().ok()?;
+    match arg.starts_with('+') {
+        true => (n < count).then_some(n),
+        false => count.checked_sub(n + 1),
+    }
+}
+
+/// `export [-n] [name[=value] ...]`, pass variables to the environment of commands
+/// `-n` stops passing them, `export` or `export -p` lists them
+fn export(args: &[String], shell: &mut Shell, output: &mut Output) -> io::Result<i32> {
+    let (exported, args) = match args.first().map(String::as_str) {
+        Some("-n") => (false, &args[1..]),
+        Some("-p") | None => {
+            list_vars(shell, output, "export", |var| var.exported)?;
+            return Ok(0);
+        }
+        _ => (true, args),
+    };
+    declare(args, shell, output, "export", |vars, name| {
+        vars.export(name, exported)
+    })
+}
+
+/// `readonly [name[=value] ...]`, forbid changing variables, or list them
+fn readonly(args: &[String], shell: &mut Shell, output: &mut Output) -> io::Result<i32> {
+    if args.is_empty() || args[0] == "-p" {
+        list_vars(shell, output, "readonly", |var| var.readonly)?;
+        return Ok(0);
+    }
+    declare(args, shell, output, "readonly", Variables::set_readonly)
+}
+
+/// set `name=value` arguments, and apply `f` to the names of all arguments
+fn declare(
+    args: &[String],
+    shell: &mut Shell,
+    output: &mut Output,
+    prog: &str,
+    f: impl Fn(&mut Variables, &str),
+) -> io::Result<i32> {
+    let mut status = 0;
+    for arg in args {
+        let name = match split_assignment(arg) {
+            Some((name, value)) => match shell.vars.set(name, value) {
+                Ok(()) => name,
+                Err(err) => {
+                    writeln!(output.err, "{}: {}", prog, err)?;
+                    status = 1;
+                    continue;
+                }
+            },
+            None if is_name(arg) => arg,
+            None => {
+                writeln!(output.err, "{}: `{}': not a valid identifier", prog, arg)?;
+                status = 1;
+                continue;
+            }
+        };
+        f(&mut shell.vars, name);
+    }
+    Ok(status)
+}
+
+/// list variables as the commands that declare them, like `export NAME=value`
+fn list_vars(
+    shell: &Shell,
+    output: &mut Output,
+    prog: &str,
+    filter: impl Fn(&Variable) -> bool,
+) -> io::Result<()> {
+    for (name, var) in shell.vars.iter().filter(|(_, var)| filter(var)) {
+        match &var.value {
+            Some(value) => writeln!(output.out, "{} {}={}", prog, name, quote(value))?,
+            None => writeln!(output.out, "{} {}", prog, name)?,
+        }
+    }
+    Ok(())
+}
+
+/// `unset [-v|-f] name ...`, remove variables or functions
+/// without an option, a function is removed if no variable has the name
+fn unset(args: &[String], shell: &mut Shell, output: &mut Output) -> io::Result<i32> {
+    let (variables, functions, args) = match args.first().map(String::as_str) {
+        Some("-v") => (true, false, &args[1..]),
+        Some("-f") => (false, true, &args[1..]),
+        _ => (true, true, args),
+    };
+    let mut status = 0;
+    for name in args {
+        if functions && !(variables && shell.vars.get_var(name).is_some()) {
+            shell.functions.remove(name);
+            continue;
+        }
+        if let Err(err) = shell.vars.unset(name) {
+            writeln!(output.err, "unset: {}", err)?;
+            status = 1;
+        }
+    }
+    Ok(status)
+}
+
+/// `local name[=value] ...`, variables of the function being run, restored
+/// when it returns
+fn local(args: &[String], shell: &mut Shell, output: &mut Output) -> io::Result<i32> {
+    let mut status = 0;
+    for arg in args {
+        let (name, value) = match split_assignment(arg) {
+            Some((name, value)) => (name, Some(value)),
+            None if is_name(arg) => (arg.as_str(), None),
+            None => {
+                writeln!(output.err, "local: `{}': not a valid identifier", arg)?;
+                status = 1;
+                continue;
+            }
+        };
+        let result = shell
+            .vars
+            .local(name)
+            .and_then(|_| value.map_or(Ok(()), |value| shell.vars.set(name, value)));
+        if let Err(err) = result {
+            writeln!(output.err, "local: {}", err)?;
+            status = 1;
+        }
+    }
+    Ok(status)
+}
+
+/// `let expr ...`, evaluate arithmetic expressions, fails if the last one is 0
+fn let_built_in(args: &[String], shell: &mut Shell, output: &mut Output) -> io::Result<i32> {
+    if args.is_empty() {
+        writeln!(output.err, "let: expression expected")?;
+        return Ok(1);
+    }
+    let mut value = 0;
+    for arg in args {
+        match arith::evaluate(arg, &mut shell.vars) {
+            Ok(result) => value = result,
+            Err(err) => {
+                writeln!(output.err, "let: {}", err)?;
+                return Ok(1);
+            }
+        }
+    }
+    Ok((value == 0) as i32)
+}
+
+/// `return [n]`, leave the function or sourced file being run with status n, `$?` by default
+fn return_built_in(args: &[String], shell: &mut Shell, output: &mut Output) -> io::Result<i32> {
+    if shell.call_depth == 0 {
+        writeln!(
+            output.err,
+            "return: can only `return' from a function or sourced file"
+        )?;
+        return Ok(1);
+    }
+    let status = match args.first().map(|arg| arg.parse::<i32>()) {
+        Some(Ok(status)) => status & 0xff,
+        Some(Err(_)) => {
+            writeln!(output.err, "return: {}: numeric argument required", args[0])?;
+            2
+        }
+        None => shell.status,
+    };
+    shell.control = Some(Control::Return);
+    Ok(status)
+}
+
+/// `source file [args ...]` or `. file [args ...]`, run the commands of a file
+/// in the current shell, with the args as positional parameters if any
+/// a file name without `/` is looked for in `$PATH`, then the current directory
+fn source(prog: &str, args: &[String], shell: &mut Shell, output: &mut Output) -> io::Result<i32> {
+    let name = match args.first() {
+        Some(name) => name,
+        None => {
+            writeln!(output.err, "{}: filename argument required", prog)?;
+            return Ok(2);
+        }
+    };
+    let path = match name.contains('/') {
+        true => None,
+        false => shell
+            .vars
+            .get("PATH")
+            .unwrap_or_default()
+            .split(':')
+            .map(|dir| Path::new(if dir.is_empty() { "." } else { dir }).join(name))
+            .find(|path| path.is_file()),
+    };
+    let text = match fs::read_to_string(path.as_deref().unwrap_or(Path::new(name))) {
+        Ok(text) => text,
+        Err(err) => {
+            writeln!(output.err, "{}: {}: {}", prog, name, strerror(&err))?;
+            return Ok(1);
+        }
+    };
+    output.out.flush()?;
+    Ok(run_file(&text, &args[1..], shell))
+}
+
+/// `break [n]` or `continue [n]`, leave n loops, going on with the last one
+/// for `continue`
+fn break_continue(
+    prog: &str,
+    args: &[String],
+    shell: &mut Shell,
+    output: &mut Output,
+) -> io::Result<i32> {
+    if shell.loop_depth == 0 {
+        writeln!(output.err, "{}: only meaningful in a loop", prog)?;
+        return Ok(0);
+    }
+    let n = match args.first().map(|arg| arg.parse::<usize>()) {
+        Some(Ok(n)) if n > 0 => n,
+        Some(_) => {
+            writeln!(output.err, "{}: {}: loop count out of range", prog, args[0])?;
+            return Ok(1);
+        }
+        None => 1,
+    };
+    let n = min(n, shell.loop_depth);
+    shell.control = Some(match prog {
+        "break" => Control::Break(n),
+        _ => Control::Continue(n),
+    });
+    Ok(0)
+}
+
+/// `test expression` or `[ expression ]`, succeed if the expression is true
+fn test(prog: &str, args: &[String], output: &mut Output) -> io::Result<i32> {
+    let args = match prog {
+        "[" => match args.split_last() {
+            Some((last, args)) if last == "]" => args,
+            _ => {
+                writeln!(output.err, "[: missing `]'")?;
+                return Ok(2);
+            }
+        },
+        _ => args,
+    };
+    match condition::evaluate(args) {
+        Ok(true) => Ok(0),
+        Ok(false) => Ok(1),
+        Err(err) => {
+            writeln!(output.err, "{}: {}", prog, err)?;
+            Ok(2)
+        }
+    }
+}
+
+/// `alias [name[=value] ...]`, define aliases or print them, all of them
+/// without arguments
+fn alias(args: &[String], shell: &mut Shell, output: &mut Output) -> io::Result<i32> {
+    if args.is_empty() || args[0] == "-p" {
+        for (name, value) in &shell.aliases {
+            writeln!(output.out, "alias {}={}", name, quote(value))?;
+        }
+        return Ok(0);
+    }
+    let mut status = 0;
+    for arg in args {
+        match arg.split_once('=') {
+            Some((name, value)) if !name.is_empty() && !name.contains('/') => {
+                shell.aliases.insert(name.to_string(), value.to_string());
+            }
+            Some(_) => {
+                writeln!(output.err, "alias: `{}': invalid alias name", arg)?;
+                status = 1;
+            }
+            None => match shell.aliases.get(arg) {
+                Some(value) => writeln!(output.out, "alias {}={}", arg, quote(value))?,
+                None => {
+                    writeln!(output.err, "alias: {}: not found", arg)?;
+                    status = 1;
+                }
+            },
+        }
+    }
+    Ok(status)
+}
+
+/// `unalias [-a] name ...`, remove aliases, all of them with `-a`
+fn unalias(args: &[String], shell: &mut Shell, output: &mut Output) -> io::Result<i32> {
+    if args.first().is_some_and(|arg| arg == "-a") {
+        shell.aliases.clear();
+        return Ok(0);
+    }
+    if args.is_empty() {
+        writeln!(output.err, "unalias: usage: unalias [-a] name [name ...]")?;
+        return Ok(2);
+    }
+    let mut status = 0;
+    for name in args {
+        if shell.aliases.remove(name).is_none() {
+            writeln!(output.err, "unalias: {}: not found", name)?;
+            status = 1;
+        }
+    }
+    Ok(status)
+}
+
+/// `set` lists variables, `set [--] args ...` sets positional parameters
+/// `set -euxnC` and `set -o name` turn options on, `+` instead of `-` turns
+/// them off, `set -o` lists them and `set +o` prints the commands to restore them
+fn set(args: &[String], shell: &mut Shell, output: &mut Output) -> io::Result<i32> {
+    if args.is_empty() {
+        for (name, var) in shell.vars.iter() {
+            if let Some(value) = &var.value {
+                writeln!(output.out, "{}={}", name, quote(value))?;
+            }
+        }
+        return Ok(0);
+    }
+    let mut i = 0;
+    // `--` sets the positional parameters even if none follow
+    let mut positional = false;
+    while let Some(arg) = args.get(i) {
+        let on = match arg.chars().next() {
+            Some('-') => true,
+            Some('+') => false,
+            _ => break,
+        };
+        i += 1;
+        match arg.as_str() {
+            "--" => {
+                positional = true;
+                break;
+            }
+            "-" | "+" => break,
+            _ => (),
+        }
+        for letter in arg[1..].chars() {
+            let name = match letter {
+                'o' => match args.get(i) {
+                    Some(name) => {
+                        i += 1;
+                        name.as_str()
+                    }
+                    None => {
+                        print_set_options(on, shell, output)?;
+                        continue;
+                    }
+                },
+                _ => match Options::SET_NAMES.iter().find(|(_, l)| *l == Some(letter)) {
+                    Some((name, _)) => name,
+                    None => {
+                        writeln!(output.err, "set: {}{}: invalid option", &arg[..1], letter)?;
+                        return Ok(2);
+                    }
+                },
+            };
+            match shell.options.set_mut(name) {
+                Some(option) => *option = on,
+                None => {
+                    writeln!(output.err, "set: {}: invalid option name", name)?;
+                    return Ok(2);
+                }
+            }
+        }
+    }
+    if positional || i < args.len() {
+        shell.args.truncate(1);
+        shell.args.extend(args[i..].iter().cloned());
+    }
+    Ok(0)
+}
+
+/// the options of `set -o`, as a list by `set -o` or as commands by `set +o`
+fn print_set_options(list: bool, shell: &mut Shell, output: &mut Output) -> io::Result<()> {
+    for (name, _) in Options::SET_NAMES {
+        let on = shell.options.set_mut(name).is_some_and(|on| *on);
+        match list {
+            true => writeln!(output.out, "{:<16}{}", name, if on { "on" } else { "off" })?,
+            false => writeln!(output.out, "set {}o {}", if on { '-' } else { '+' }, name)?,
+        }
+    }
+    Ok(())
+}
+
+fn exit_built_in(args: &[String], shell: &mut Shell, output: &mut Output) -> io::Result<i32> {
+    let status = match args.first().map(|arg| arg.parse::<i32>()) {
+        Some(Ok(status)) => status & 0xff,
+        Some(Err(_)) => {
+            writeln!(output.err, "exit: {}: numeric argument required", args[0])?;
+            2
+        }
+        None => shell.status,
+    };
+    // the `exit` command line is kept too, by the shell reading it only
+    if shell.interactive && !shell.subshell {
+        shell.history.finish(status).ok();
+    }
+    output.out.flush().ok();
+    exit_shell(status, shell);
+}
+
+fn jobs(shell: &mut Shell, output: &mut Output) -> io::Result<i32> {
+    shell.jobs.reap();
+    for line in shell.jobs.describe() {
+        writeln!(output.out, "{}", line)?;
+    }
+    Ok(0)
+}
+
+fn fg(args: &[String], shell: &mut Shell, output: &mut Output) -> io::Result<i32> {
+    let id = match shell.jobs.find(args.first().map(String::as_str)) {
+        Ok(id) => id,
+        Err(err) => {
+            writeln!(output.err, "fg: {}", err)?;
+            return Ok(1);
+        }
+    };
+    let mut job = shell.jobs.remove(id).unwrap();
+    writeln!(output.out, "{}", job.command)?;
+    output.out.flush()?;
+    if let Err(err) = job.resume() {
+        writeln!(output.err, "fg: {}", err.desc())?;
+        return Ok(1);
+    }
+    Ok(wait_foreground(job, shell).last().copied().unwrap_or(0))
+}
+
+fn bg(args: &[String], shell: &mut Shell, output: &mut Output) -> io::Result<i32> {
+    let job = match shell.jobs.find(args.first().map(String::as_str)) {
+        Ok(id) => shell.jobs.get_mut(id).unwrap(),
+        Err(err) => {
+            writeln!(output.err, "bg: {}", err)?;
+            return Ok(1);
+        }
+    };
+    if job.state() != JobState::Stopped {
+        writeln!(output.err, "bg: job {} already in background", job.id)?;
+        return Ok(0);
+    }
+    if let Err(err) = job.resume() {
+        writeln!(output.err, "bg: {}", err.desc())?;
+        return Ok(1);
+    }
+    writeln!(output.out, "[{}] {} &", job.id, job.command)?;
+    Ok(0)
+}
+
+/// `shopt [-s | -u] [name ...]`, set, unset or show shell options
+fn shopt(args: &[String], shell: &mut Shell, output: &mut Output) -> io::Result<i32> {
+    let (set, names) = match args.first().map(String::as_str) {
+        Some("-s") => (Some(true), &args[1..]),
+        Some("-u") => (Some(false), &args[1..]),
+        _ => (None, args),
+    };
+    let names: Vec<&str> = match names.is_empty() {
+        true => Options::NAMES.to_vec(),
+        false => names.iter().map(String::as_str).collect(),
+    };
+    let mut status = 0;
+    for name in names {
+        let option = match shell.options.get_mut(name) {
+            Some(option) => option,
+            None => {
+                writeln!(output.err, "shopt: {}: invalid shell option name", name)?;
+                status = 1;
+                continue;
+            }
+        };
+        match set {
+            Some(value) if !args[1..].is_empty() => *option = value,
+            _ => {
+                // only the options in that state are listed by `-s` or `-u` alone
+                if set.is_none_or(|value| value == *option) {
+                    let state = if *option { "on" } else { "off" };
+                    writeln!(output.out, "{:<16}{}", name, state)?;
+                }
+                if set.is_none() && !*option && !args.is_empty() {
+                    status = 1;
+                }
+            }
+        }
+    }
+    Ok(status)
+}
+
+/// `complete [-d | -f | -c | -v | -W words | -r] name ...`, set how the arguments
+/// of commands complete, or list it with no options
+fn complete(args: &[String], shell: &mut Shell, output: &mut Output) -> io::Result<i32> {
+    let (option, names) = match args.first().map(String::as_str) {
+        Some("-W") if args.len() > 1 => {
+            let words = args[1].split_whitespace().map(str::to_string).collect();
+            (Some(Completer::Words(words)), &args[2..])
+        }
+        Some("-d") => (Some(Completer::Dirs), &args[1..]),
+        Some("-f") => (Some(Completer::Files), &args[1..]),
+        Some("-c") => (Some(Completer::Commands), &args[1..]),
+        Some("-v") => (Some(Completer::Variables), &args[1..]),
+        Some("-r") => {
+            let mut status = 0;
+            for name in &args[1..] {
+                if !shell.completion.unregister(name) {
+                    writeln!(
+                        output.err,
+                        "complete: {}: no completion specification",
+                        name
+                    )?;
+                    status = 1;
+                }
+            }
+            return Ok(status);
+        }
+        Some("-p") | None => {
+            for (name, completer) in shell.completion.commands() {
+                let option = match completer {
+                    Completer::Files => "-f".to_string(),
+                    Completer::Dirs => "-d".to_string(),
+                    Completer::Commands => "-c".to_string(),
+                    Completer::Variables => "-v".to_string(),
+                    Completer::Words(words) => format!("-W '{}'", words.join(" ")),
+                    Completer::Function(_) => "(built-in)".to_string(),
+                };
+                writeln!(output.out, "complete {} {}", option, name)?;
+            }
+            return Ok(0);
+        }
+        Some(_) => (None, args),
+    };
+    match option {
+        Some(completer) if !names.is_empty() => {
+            for name in names {
+                shell.completion.register(name, completer.clone());
+            }
+            Ok(0)
+        }
+        _ => {
+            writeln!(
+                output.err,
+                "complete: usage: complete [-d | -f | -c | -v | -W words | -r] name ..."
+            )?;
+            Ok(2)
+        }
+    }
+}
+
+/// describe an io error without the "(os error N)" suffix
+pub fn strerror(err: &io::Error) -> String {
+    match err.raw_os_error() {
+        Some(errno) => Errno::from_i32(errno).desc().to_string(),
+        None => err.to_string(),
+    }
+}
+
+/// `kill [-s SIG | -SIG] %job|pid ...` and `kill -l`
+fn kill_built_in(args: &[String], shell: &mut Shell, output: &mut Output) -> io::Result<i32> {
+    let mut args = args.iter().peekable();
+    let mut signal = Signal::SIGTERM;
+    match args.peek().map(|arg| arg.as_str()) {
+        Some("-l") => {
+            let names: Vec<&str> = Signal::iterator().map(|signal| signal.as_str()).collect();
+            writeln!(output.out, "{}", names.join(" "))?;
+            return Ok(0);
+        }
+        Some("-s") => {
+            args.next();
+            match args.next().and_then(|name| parse_signal(name)) {
+                Some(sig) => signal = sig,
+                None => {
+                    writeln!(output.err, "kill: invalid signal specification")?;
+                    return Ok(1);
+                }
+            }
+        }
+        Some(arg) if arg.starts_with('-') => match parse_signal(&arg[1..]) {
+            Some(sig) => {
+                signal = sig;
+                args.next();
+            }
+            None => {
+                writeln!(
+                    output.err,
+                    "kill: {}: invalid signal specification",
+                    &arg[1..]
+                )?;
+                return Ok(1);
+            }
+        },
+        _ => (),
+    }
+    if args.peek().is_none() {
+        writeln!(
+            output.err,
+            "kill: usage: kill [-s sigspec | -sigspec] pid | jobspec ... or kill -l"
+        )?;
+        return Ok(2);
+    }
+
+    let mut status = 0;
+    for target in args {
+        let result = if target.starts_with('%') {
+            match shell.jobs.find(Some(target)) {
+                Ok(id) => {
+                    let job = shell.jobs.get_mut(id).unwrap();
+                    // a stopped job has to run to handle the signal
+                    job.signal(signal).and_then(|_| match job.state() {
+                        JobState::Stopped if signal != Signal::SIGCONT => job.resume(),
+                        _ => Ok(()),
+                    })
+                }
+                Err(err) => {
+                    writeln!(output.err, "kill: {}", err)?;
+                    status = 1;
+                    continue;
+                }
+            }
+        } else {
+            match target.parse::<i32>() {
+                Ok(pid) => kill(Pid::from_raw(pid), signal),
+                Err(_) => {
+                    writeln!(
+                        output.err,
+                        "kill: {}: arguments must be process or job IDs",
+                        target
+                    )?;
+                    status = 1;
+                    continue;
+                }
+            }
+        };
+        if let Err(err) = result {
+            writeln!(output.err, "kill: {}: {}", target, err.desc())?;
+            status = 1;
+        }
+    }
+    Ok(status)
+}
+
+/// `trap action signal...` runs the action when the shell gets one of the
+/// signals, or leaves with `EXIT`; an empty action ignores them and `-` or no
+/// action resets them. `trap` or `trap -p [signal...]` lists the traps, and
+/// `trap -l` the signals
+fn trap(args: &[String], shell: &mut Shell, output: &mut Output) -> io::Result<i32> {
+    let (list, args) = match args.first().map(String::as_str) {
+        Some("-l") => {
+            for signal in Signal::iterator() {
+                writeln!(output.out, "{:2}) {}", signal as i32, signal.as_str())?;
+            }
+            return Ok(0);
+        }
+        Some("-p") => (true, &args[1..]),
+        Some("--") => (args.len() == 1, &args[1..]),
+        None => (true, args),
+        Some(_) => (false, args),
+    };
+    let mut status = 0;
+    let mut conditions = Vec::new();
+    // a lone condition, or a number first, is reset
+    let (action, names) = match args.first() {
+        Some(_) if list => (None, args),
+        Some(first) if args.len() == 1 || first.parse::<u32>().is_ok() => (Some("-"), args),
+        Some(first) => (Some(first.as_str()), &args[1..]),
+        None => (None, args),
+    };
+    for name in names {
+        match parse_trap(name) {
+            Some(Some(Signal::SIGKILL | Signal::SIGSTOP)) if action.is_some() => {
+                writeln!(output.err, "trap: {}: cannot be trapped", name)?;
+                status = 1;
+            }
+            Some(condition) => conditions.push(condition),
+            None => {
+                writeln!(output.err, "trap: {}: invalid signal specification", name)?;
+                status = 1;
+            }
+        }
+    }
+    let action = match action {
+        Some(action) => action,
+        None => {
+            let number = |condition: &Option<Signal>| condition.map_or(0, |signal| signal as i32);
+            let numbers: Vec<i32> = conditions.iter().map(number).collect();
+            for (&number, action) in &shell.traps {
+                if !numbers.is_empty() && !numbers.contains(&number) {
+                    continue;
+                }
+                let name = match Signal::try_from(number) {
+                    Ok(signal) => signal.as_str(),
+                    Err(_) => "EXIT",
+                };
+                writeln!(output.out, "trap -- {} {}", quote(action), name)?;
+            }
+            return Ok(status);
+        }
+    };
+    for condition in conditions {
+        let number = condition.map_or(0, |signal| signal as i32);
+        let disposition = match action {
+            "-" => {
+                shell.traps.remove(&number);
+                None
+            }
+            "" => Some(Disposition::Ignore),
+            _ => Some(Disposition::Catch),
+        };
+        if action != "-" {
+            shell.traps.insert(number, action.to_string());
+        }
+        if let Some(signal) = condition {
+            signals::set_trap(signal, disposition);
+        }
+    }
+    Ok(status)
+}
+
+/// the signal of a `trap` condition, None for `EXIT`
+fn parse_trap(name: &str) -> Option<Option<Signal>> {
+    match name.to_uppercase().as_str() {
+        "EXIT" | "0" => Some(None),
+        _ => parse_signal(name).map(Some),
+    }
+}
+
+/// parse a signal given by number, or by name with or without `SIG`
+fn parse_signal(name: &str) -> Option<Signal> {
+    if let Ok(number) = name.parse::<i32>() {
+        return Signal::try_from(number).ok();
+    }
+    let name = name.to_uppercase();
+    let name = if name.starts_with("SIG") {
+        name
+    } else {
+        "SIG".to_string() + &name
+    };
+    name.parse().ok()
+}
+
+/// `type [-aftpP] name ...`, what names run as commands: an alias, a keyword,
+/// a function, a built-in or a program. `-t` prints only which one, `-p` the
+/// path of a program, `-P` the path even if something else comes first, `-a`
+/// all of them and `-f` leaves functions out
+fn type_built_in(args: &[String], shell: &mut Shell, output: &mut Output) -> io::Result<i32> {
+    let (mut all, mut terse, mut path, mut path_only, mut no_functions) =
+        (false, false, false, false, false);
+    let mut names = args;
+    while let Some(arg) = names
+        .first()
+        .filter(|arg| arg.starts_with('-') && arg.len() > 1)
+    {
+        names = &names[1..];
+        if arg == "--" {
+            break;
+        }
+        for option in arg[1..].chars() {
+            match option {
+                'a' => all = true,
+                't' => terse = true,
+                'p' => path = true,
+                'P' => path_only = true,
+                'f' => no_functions = true,
+                _ => {
+                    writeln!(output.err, "type: -{}: invalid option", option)?;
+                    return Ok(2);
+                }
+            }
+        }
+    }
+    let mut status = 0;
+    for name in names {
+        let mut kinds = resolve::resolve(name, all, path_only, shell);
+        if no_functions {
+            kinds.retain(|kind| *kind != Kind::Function);
+        }
+        if kinds.is_empty() {
+            if !(terse || path || path_only) {
+                writeln!(output.err, "type: {}: not found", name)?;
+            }
+            status = 1;
+        }
+        for kind in kinds {
+            match kind {
+                _ if terse => writeln!(output.out, "{}", kind.name())?,
+                Kind::File(file, _) if path || path_only => writeln!(output.out, "{}", file)?,
+                _ if path || path_only => (),
+                _ => describe_command(name, &kind, shell, output)?,
+            }
+        }
+    }
+    Ok(status)
+}
+
+/// what a name runs as a command, as told by `type` and `command -V`
+fn describe_command(name: &str, kind: &Kind, shell: &Shell, output: &mut Output) -> io::Result<()> {
+    match kind {
+        Kind::Alias(text) => writeln!(output.out, "{} is aliased to `{}'", name, text),
+        Kind::Keyword => writeln!(output.out, "{} is a shell keyword", name),
+        Kind::Function => {
+            writeln!(output.out, "{} is a function", name)?;
+            match shell.functions.get(name) {
+                Some(body) => writeln!(output.out, "{}() {}", name, body),
+                None => Ok(()),
+            }
+        }
+        Kind::BuiltIn => writeln!(output.out, "{} is a shell builtin", name),
+        Kind::File(file, true) => writeln!(output.out, "{} is hashed ({})", name, file),
+        Kind::File(file, false) => writeln!(output.out, "{} is {}", name, file),
+    }
+}
+
+/// `command -v name ...` prints how names run as commands, the path of a
+/// program or the `alias` command of an alias, `-V` like `type`
+/// `command [-p] name args ...` itself is run by the executor, which skips
+/// functions for it
+fn command(args: &[String], shell: &mut Shell, output: &mut Output) -> io::Result<i32> {
+    let (verbose, names) = match args.first().map(String::as_str) {
+        Some("-v") => (false, &args[1..]),
+        Some("-V") => (true, &args[1..]),
+        Some(arg) if arg.starts_with('-') && arg != "--" => {
+            writeln!(output.err, "command: {}: invalid option", arg)?;
+            return Ok(2);
+        }
+        _ => return Ok(0),
+    };
+    let mut status = 0;
+    for name in names {
+        let kind = match resolve::resolve(name, false, false, shell).pop() {
+            Some(kind) => kind,
+            None => {
+                if verbose {
+                    writeln!(output.err, "command: {}: not found", name)?;
+                }
+                status = 1;
+                continue;
+            }
+        };
+        match kind {
+            _ if verbose => describe_command(name, &kind, shell, output)?,
+            Kind::Alias(text) => writeln!(output.out, "alias {}={}", name, quote(&text))?,
+            Kind::File(file, _) => writeln!(output.out, "{}", file)?,
+            _ => writeln!(output.out, "{}", name)?,
+        }
+    }
+    Ok(status)
+}
+
+/// `hash [name ...]` remembers where programs are in `$PATH`, and with no
+/// names lists them with how many times they were run. `-r` forgets them all,
+/// `-d` the names, `-t` prints their paths and `-p path name` sets one
+fn hash(args: &[String], shell: &mut Shell, output: &mut Output) -> io::Result<i32> {
+    let mut names = args;
+    let mut option = None;
+    while let Some(arg) = names.first() {
+        match arg.as_str() {
+            "-r" => shell.hash.clear(),
+            "-d" | "-t" => option = Some(arg.as_str()),
+            "-p" if names.len() > 2 => {
+                shell.hash.insert(&names[2], &names[1]);
+                return Ok(0);
+            }
+            "--" => {
+                names = &names[1..];
+                break;
+            }
+            _ if arg.starts_with('-') => {
+                writeln!(output.err, "hash: {}: invalid option", arg)?;
+                writeln!(
+                    output.err,
+                    "hash: usage: hash [-r] [-p path] [-dt] [name ...]"
+                )?;
+                return Ok(2);
+            }
+            _ => break,
+        }
+        names = &names[1..];
+    }
+    if names.is_empty() {
+        if let Some(option) = option {
+            writeln!(output.err, "hash: {}: option requires an argument", option)?;
+            return Ok(1);
+        }
+        if args.is_empty() {
+            let mut entries = shell.hash.iter().peekable();
+            if entries.peek().is_none() {
+                writeln!(output.out, "hash: hash table empty")?;
+            } else {
+                writeln!(output.out, "hits\tcommand")?;
+                for (_, file, hits) in entries {
+                    writeln!(output.out, "{:>4}\t{}", hits, file)?;
+                }
+            }
+        }
+        return Ok(0);
+    }
+    let path = shell.vars.get("PATH").unwrap_or_default().to_string();
+    let mut status = 0;
+    for name in names {
+        let found = match option {
+            Some("-d") => shell.hash.remove(name),
+            Some(_) => {
+                let file = shell.hash.get(name);
+                match (file, names.len() > 1) {
+                    (Some(file), true) => writeln!(output.out, "{}\t{}", name, file)?,
+                    (Some(file), false) => writeln!(output.out, "{}", file)?,
+                    (None, _) => (),
+                }
+                file.is_some()
+            }
+            // built-ins and functions are not looked for
+            None if is_built_in(name) || shell.functions.contains_key(name) => true,
+            None => shell.hash.remember(name, &path).is_some(),
+        };
+        if !found {
+            writeln!(output.err, "hash: {}: not found", name)?;
+            status = 1;
+        }
+    }
+    Ok(status)
+}
@@ -0,0 +1,342 @@
+use crate::builtin::BUILT_INS;
+use crate::resolve::is_executable;
+use crate::shell::Shell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// a possible completion of the word under the cursor
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Candidate {
+    /// the text to replace the word with, escaped as needed
+    pub text: String,
+    /// how it is listed, like a file name without its directory
+    pub display: String,
+}
+
+/// candidates for the word that starts at byte `start` of the line
+#[derive(Debug, Default)]
+pub struct Completions {
+    pub start: usize,
+    pub candidates: Vec<Candidate>,
+}
+
+/// what the arguments of a command complete to
+#[derive(Clone)]
+pub enum Completer {
+    Files,
+    Dirs,
+    Commands,
+    Variables,
+    /// a fixed list of words, like subcommands
+    Words(Vec<String>),
+    /// a function of the words before the one completed and the word itself,
+    /// returns the possible words, or None to complete file names
+    Function(fn(&[String], &str) -> Option<Vec<String>>),
+}
+
+/// per-command completers, commands without one complete file names
+pub struct Completion {
+    completers: HashMap<String, Completer>,
+}
+
+impl Default for Completion {
+    fn default() -> Completion {
+        let mut completion = Completion {
+            completers: HashMap::new(),
+        };
+        completion.register("cd", Completer::Dirs);
+        completion.register("export", Completer::Variables);
+        completion.register("git", Completer::Function(git));
+        completion
+    }
+}
+
+impl Completion {
+    /// let the arguments of a command complete with the completer
+    pub fn register(&mut self, command: &str, completer: Completer) {
+        self.completers.insert(command.to_string(), completer);
+    }
+
+    pub fn unregister(&mut self, command: &str) -> bool {
+        self.completers.remove(command).is_some()
+    }
+
+    /// commands with a completer, sorted
+    pub fn commands(&self) -> Vec<(&String, &Completer)> {
+        let mut commands: Vec<_> = self.completers.iter().collect();
+        commands.sort_by_key(|(command, _)| *command);
+        commands
+    }
+
+    fn get(&self, command: &str) -> Option<&Completer> {
+        self.completers.get(command)
+    }
+}
+
+/// words followed by a command
+const KEYWORDS: [&str; 8] = ["if", "then", "elif", "else", "while", "until", "do", "{"];
+
+/// complete the word at the end of `line`, the text before the cursor
+pub fn complete(line: &str, shell: &Shell) -> Completions {
+    let (start, words) = split_words(line);
+    let word = words.last().cloned().unwrap_or_default();
+    let raw = &line[start..];
+    let quote = raw.chars().next().filter(|&c| c == '\'' || c == '"');
+    let mut before = &words[..words.len().saturating_sub(1)];
+    // a command starts after these, like after `;`
+    while before
+        .first()
+        .is_some_and(|word| KEYWORDS.contains(&word.as_str()))
+    {
+        before = &before[1..];
+    }
+
+    let mut candidates = if let Some(name) = raw.strip_prefix("${") {
+        variables(shell, name, "${", "}")
+    } else if let Some(name) = raw.strip_prefix('$') {
+        variables(shell, name, "$", "")
+    } else if let Some(prefix) = raw.strip_prefix('!').filter(|_| before.is_empty()) {
+        history_events(prefix, shell)
+    } else if before.is_empty() && !word.contains('/') {
+        commands(&word, shell)
+    } else if before.is_empty() {
+        paths(shell, &word, quote, is_executable)
+    } else {
+        match shell.completion.get(&before[0]) {
+            Some(Completer::Dirs) => paths(shell, &word, quote, |_| false),
+            Some(Completer::Commands) => commands(&word, shell),
+            Some(Completer::Variables) => variables(shell, &word, "", ""),
+            Some(Completer::Words(list)) => matching_words(list, &word),
+            Some(Completer::Function(f)) => match f(before, &word) {
+                Some(list) => matching_words(&list, &word),
+                None => paths(shell, &word, quote, |_| true),
+            },
+            Some(Completer::Files) | None => paths(shell, &word, quote, |_| true),
+        }
+    };
+    candidates.sort();
+    candidates.dedup();
+    Completions { start, candidates }
+}
+
+/// the words of a command line as far as completion cares: unquoted, from the
+/// start of the last command. return the byte where the last word starts too
+fn split_words(line: &str) -> (usize, Vec<String>) {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut start = 0;
+    let mut in_word = false;
+    let mut quote = None;
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') => word.extend(chars.next().map(|(_, c)| c)),
+            (Some(_), c) => word.push(c),
+            (None, '\'' | '"') => {
+                if !in_word {
+                    in_word = true;
+                    start = i;
+                }
+                quote = Some(c);
+            }
+            (None, '\\') => {
+                if !in_word {
+                    in_word = true;
+                    start = i;
+                }
+                word.extend(chars.next().map(|(_, c)| c));
+            }
+            (None, c) if c.is_whitespace() || "|&;()<>".contains(c) => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+                // a new command starts after these
+                if "|&;(".contains(c) {
+                    words.clear();
+                }
+                start = i + c.len_utf8();
+            }
+            (None, c) => {
+                if !in_word {
+                    in_word = true;
+                    start = i;
+                }
+                word.push(c);
+            }
+        }
+    }
+    if !in_word {
+        start = line.len();
+    }
+    words.push(word);
+    (start, words)
+}
+
+/// put backslashes before chars the lexer would take specially
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        if c.is_whitespace() || "\\'\"$`&|;()<>*?[]#!{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn matching_words(list: &[String], word: &str) -> Vec<Candidate> {
+    list.iter()
+        .filter(|candidate| candidate.starts_with(word))
+        .map(|candidate| Candidate {
+            text: escape(candidate),
+            display: candidate.clone(),
+        })
+        .collect()
+}
+
+/// aliases, functions, built-ins and programs in `$PATH`
+fn commands(word: &str, shell: &Shell) -> Vec<Candidate> {
+    let mut names: Vec<String> = BUILT_INS.iter().map(|name| name.to_string()).collect();
+    names.extend(shell.aliases.keys().cloned());
+    names.extend(shell.functions.keys().cloned());
+    for dir in shell.vars.get("PATH").unwrap_or_default().split(':') {
+        let entries = match fs::read_dir(if dir.is_empty() { "." } else { dir }) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            match entry.file_name().into_string() {
+                Ok(name) if name.starts_with(word) && is_executable(&entry.path()) => {
+                    names.push(name)
+                }
+                _ => (),
+            }
+        }
+    }
+    matching_words(&names, word)
+}
+
+/// names of variables, between a prefix and a suffix like `${` and `}`
+fn variables(shell: &Shell, name: &str, prefix: &str, suffix: &str) -> Vec<Candidate> {
+    shell
+        .vars
+        .iter()
+        .map(|(key, _)| key.clone())
+        .filter(|key| key.starts_with(name))
+        .map(|key| Candidate {
+            text: format!("{}{}{}", prefix, key, suffix),
+            display: key,
+        })
+        .collect()
+}
+
+/// history lines starting with the prefix, replacing `!prefix` as a whole
+fn history_events(prefix: &str, shell: &Shell) -> Vec<Candidate> {
+    (0..shell.history.size())
+        .filter_map(|index| shell.history.rget(index))
+        .filter(|line| line.starts_with(prefix))
+        .map(|line| Candidate {
+            text: line.clone(),
+            display: line.clone(),
+        })
+        .collect()
+}
+
+/// files and directories the word can be a path to, `~/` included
+/// directories always match, files only if `accept_file` says so
+fn paths(
+    shell: &Shell,
+    word: &str,
+    quote: Option<char>,
+    accept_file: impl Fn(&Path) -> bool,
+) -> Vec<Candidate> {
+    let (dir, name) = match word.rfind('/') {
+        Some(index) => word.split_at(index + 1),
+        None => ("", word),
+    };
+    let expanded = match dir.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            shell.vars.get("HOME").unwrap_or_default().to_string() + rest
+        }
+        _ => dir.to_string(),
+    };
+    let entries = match fs::read_dir(if expanded.is_empty() { "." } else { &expanded }) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut candidates = Vec::new();
+    for entry in entries.flatten() {
+        let file_name = match entry.file_name().into_string() {
+            Ok(file_name) => file_name,
+            Err(_) => continue,
+        };
+        // hidden files only when asked for with a dot
+        if !file_name.starts_with(name) || (file_name.starts_with('.') && !name.starts_with('.')) {
+            continue;
+        }
+        let path = entry.path();
+        let is_dir = path.is_dir();
+        if !is_dir && !accept_file(&path) {
+            continue;
+        }
+        // `~` stays as typed, the rest is quoted the way the word is
+        let (home, rest) = match dir.starts_with('~') {
+            true => dir.split_at(1),
+            false => ("", dir),
+        };
+        let full = format!("{}{}", rest, file_name);
+        let mut text = match quote {
+            Some(quote) => format!("{}{}{}", home, quote, full),
+            None => format!("{}{}", home, escape(&full)),
+        };
+        if is_dir {
+            text.push('/');
+        } else if let Some(quote) = quote {
+            text.push(quote);
+        }
+        candidates.push(Candidate {
+            text,
+            display: file_name + if is_dir { "/" } else { "" },
+        });
+    }
+    candidates
+}
+
+/// subcommands of git, files after them
+fn git(before: &[String], _: &str) -> Option<Vec<String>> {
+    if before.len() > 1 {
+        return None;
+    }
+    let subcommands = [
+        "add",
+        "bisect",
+        "branch",
+        "checkout",
+        "cherry-pick",
+        "clone",
+        "commit",
+        "diff",
+        "fetch",
+        "grep",
+        "init",
+        "log",
+        "merge",
+        "mv",
+        "pull",
+        "push",
+        "rebase",
+        "reset",
+        "restore",
+        "revert",
+        "rm",
+        "show",
+        "stash",
+        "status",
+        "switch",
+        "tag",
+    ];
+    Some(subcommands.iter().map(|name| name.to_string()).collect())
+}
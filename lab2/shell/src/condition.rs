@@ -0,0 +1,163 @@
+use nix::sys::stat::{lstat, stat, Mode, SFlag};
+use nix::unistd::{access, isatty, AccessFlags};
+use std::os::unix::fs::MetadataExt;
+
+/// evaluate the arguments of `test` or `[`, without the closing `]`
+/// `!`, `-a`, `-o` and parentheses combine the predicates
+pub fn evaluate(args: &[String]) -> Result<bool, String> {
+    let mut parser = Parser { args, pos: 0 };
+    if args.is_empty() {
+        return Ok(false);
+    }
+    let result = parser.or()?;
+    match args.get(parser.pos) {
+        Some(arg) => Err(format!("{}: unexpected argument", arg)),
+        None => Ok(result),
+    }
+}
+
+struct Parser<'a> {
+    args: &'a [String],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self, offset: usize) -> Option<&str> {
+        self.args.get(self.pos + offset).map(String::as_str)
+    }
+
+    fn next(&mut self) -> Result<&str, String> {
+        let arg = self.args.get(self.pos).ok_or("argument expected")?;
+        self.pos += 1;
+        Ok(arg)
+    }
+
+    fn or(&mut self) -> Result<bool, String> {
+        let mut result = self.and()?;
+        while self.peek(0) == Some("-o") {
+            self.pos += 1;
+            result |= self.and()?;
+        }
+        Ok(result)
+    }
+
+    fn and(&mut self) -> Result<bool, String> {
+        let mut result = self.not()?;
+        while self.peek(0) == Some("-a") {
+            self.pos += 1;
+            result &= self.not()?;
+        }
+        Ok(result)
+    }
+
+    fn not(&mut self) -> Result<bool, String> {
+        // a lone `!` is a non-empty string
+        if self.peek(0) == Some("!") && self.peek(1).is_some() {
+            self.pos += 1;
+            return Ok(!self.not()?);
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<bool, String> {
+        // a binary operator goes first, so that `[ -n = -n ]` compares strings
+        if let (Some(left), Some(op), Some(right)) = (self.peek(0), self.peek(1), self.peek(2)) {
+            if BINARY.contains(&op) {
+                let result = binary(left, op, right)?;
+                self.pos += 3;
+                return Ok(result);
+            }
+        }
+        if self.peek(0) == Some("(") && self.peek(1).is_some() {
+            self.pos += 1;
+            let result = self.or()?;
+            return match self.next()? {
+                ")" => Ok(result),
+                arg => Err(format!("{}: `)' expected", arg)),
+            };
+        }
+        if let (Some(op), Some(operand)) = (self.peek(0), self.peek(1)) {
+            if UNARY.contains(&op) {
+                let result = unary(op, operand)?;
+                self.pos += 2;
+                return Ok(result);
+            }
+        }
+        Ok(!self.next()?.is_empty())
+    }
+}
+
+/// operators taking one argument, like `-f file`
+const UNARY: [&str; 19] = [
+    "-e", "-f", "-d", "-r", "-w", "-x", "-s", "-L", "-h", "-p", "-S", "-b", "-c", "-u", "-g", "-k",
+    "-z", "-n", "-t",
+];
+
+/// operators between two arguments, like `a = b`
+const BINARY: [&str; 14] = [
+    "=", "==", "!=", "<", ">", "-eq", "-ne", "-lt", "-le", "-gt", "-ge", "-nt", "-ot", "-ef",
+];
+
+fn unary(op: &str, operand: &str) -> Result<bool, String> {
+    let file_type = |kind: SFlag| {
+        stat(operand).is_ok_and(|st| SFlag::from_bits_truncate(st.st_mode) & SFlag::S_IFMT == kind)
+    };
+    let mode = |bits: Mode| stat(operand).is_ok_and(|st| st.st_mode & bits.bits() != 0);
+    Ok(match op {
+        "-e" => stat(operand).is_ok(),
+        "-f" => file_type(SFlag::S_IFREG),
+        "-d" => file_type(SFlag::S_IFDIR),
+        "-p" => file_type(SFlag::S_IFIFO),
+        "-S" => file_type(SFlag::S_IFSOCK),
+        "-b" => file_type(SFlag::S_IFBLK),
+        "-c" => file_type(SFlag::S_IFCHR),
+        "-L" | "-h" => lstat(operand).is_ok_and(|st| {
+            SFlag::from_bits_truncate(st.st_mode) & SFlag::S_IFMT == SFlag::S_IFLNK
+        }),
+        "-r" => access(operand, AccessFlags::R_OK).is_ok(),
+        "-w" => access(operand, AccessFlags::W_OK).is_ok(),
+        "-x" => access(operand, AccessFlags::X_OK).is_ok(),
+        "-s" => stat(operand).is_ok_and(|st| st.st_size > 0),
+        "-u" => mode(Mode::S_ISUID),
+        "-g" => mode(Mode::S_ISGID),
+        "-k" => mode(Mode::S_ISVTX),
+        "-z" => operand.is_empty(),
+        "-n" => !operand.is_empty(),
+        "-t" => isatty(integer(operand)? as i32).unwrap_or(false),
+        _ => unreachable!(),
+    })
+}
+
+fn binary(left: &str, op: &str, right: &str) -> Result<bool, String> {
+    let modified = |path: &str| {
+        std::fs::metadata(path)
+            .ok()
+            .map(|meta| (meta.mtime(), meta.mtime_nsec()))
+    };
+    Ok(match op {
+        "=" | "==" => left == right,
+        "!=" => left != right,
+        "<" => left < right,
+        ">" => left > right,
+        "-eq" => integer(left)? == integer(right)?,
+        "-ne" => integer(left)? != integer(right)?,
+        "-lt" => integer(left)? < integer(right)?,
+        "-le" => integer(left)? <= integer(right)?,
+        "-gt" => integer(left)? > integer(right)?,
+        "-ge" => integer(left)? >= integer(right)?,
+        // a file that does not exist is older than any other
+        "-nt" => modified(left) > modified(right),
+        "-ot" => modified(left) < modified(right),
+        "-ef" => match (stat(left), stat(right)) {
+            (Ok(left), Ok(right)) => left.st_dev == right.st_dev && left.st_ino == right.st_ino,
+            _ => false,
+        },
+        _ => unreachable!(),
+    })
+}
+
+fn integer(text: &str) -> Result<i64, String> {
+    text.trim()
+        .parse()
+        .map_err(|_| format!("{}: integer expression expected", text))
+}
@@ -0,0 +1,123 @@
+use crate::shell::Shell;
+use crate::vars::Variables;
+use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+/// set `PWD` to the current directory when started, keeping the one from the
+/// environment if it names the same directory through symbolic links
+pub fn init(vars: &mut Variables) {
+    let pwd = vars.get("PWD").unwrap_or_default();
+    if !(pwd.starts_with('/') && same_file(pwd, ".")) {
+        if let Ok(cwd) = env::current_dir() {
+            vars.set("PWD", &cwd.to_string_lossy()).ok();
+        }
+    }
+    vars.export("PWD", true);
+}
+
+/// the current directory as the user got there, through symbolic links
+pub fn current(shell: &Shell) -> String {
+    match shell.vars.get("PWD") {
+        Some(pwd) if pwd.starts_with('/') && same_file(pwd, ".") => pwd.to_string(),
+        _ => env::current_dir()
+            .map(|cwd| cwd.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    }
+}
+
+fn same_file(a: impl AsRef<Path>, b: impl AsRef<Path>) -> bool {
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+/// an absolute path without `.`, `..` and repeated slashes, by the names
+/// only, so that `..` goes back over a symbolic link
+pub fn normalize(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => (),
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    "/".to_string() + &parts.join("/")
+}
+
+/// the directory `dir` names from the current one, `CDPATH` is searched for
+/// a relative one not starting with `.` or `..`. the second value is whether
+/// it was found through `CDPATH`, when `cd` prints where it went
+pub fn resolve(dir: &str, shell: &Shell) -> (String, bool) {
+    let pwd = current(shell);
+    let relative = |base: &str| match dir.starts_with('/') {
+        true => dir.to_string(),
+        false => format!("{}/{}", base, dir),
+    };
+    let first = dir.split('/').next().unwrap_or_default();
+    if !dir.starts_with('/') && first != "." && first != ".." {
+        for base in shell
+            .vars
+            .get("CDPATH")
+            .into_iter()
+            .flat_map(|cdpath| cdpath.split(':'))
+        {
+            // an empty entry is the current directory
+            let (base, searched) = match base {
+                "" => (pwd.clone(), false),
+                _ if base.starts_with('/') => (base.to_string(), true),
+                _ => (format!("{}/{}", pwd, base), true),
+            };
+            let path = relative(&base);
+            if Path::new(&path).is_dir() {
+                return (path, searched);
+            }
+        }
+    }
+    (relative(&pwd), false)
+}
+
+/// go to a directory and update `PWD` and `OLDPWD`
+/// `physical` follows symbolic links to set `PWD`, and otherwise `..` goes back
+/// over them by the names in the path
+pub fn change(path: &str, physical: bool, shell: &mut Shell) -> io::Result<()> {
+    let old = current(shell);
+    let pwd = match physical {
+        true => {
+            env::set_current_dir(path)?;
+            env::current_dir()?.to_string_lossy().into_owned()
+        }
+        false => {
+            let logical = normalize(path);
+            // like `dir/..` where dir is not there, fall back to the real path
+            match env::set_current_dir(&logical) {
+                Ok(()) => logical,
+                Err(_) => {
+                    env::set_current_dir(path)?;
+                    env::current_dir()?.to_string_lossy().into_owned()
+                }
+            }
+        }
+    };
+    shell.vars.set("OLDPWD", &old).ok();
+    shell.vars.export("OLDPWD", true);
+    shell.vars.set("PWD", &pwd).ok();
+    Ok(())
+}
+
+/// a directory with the home directory as `~`, as `dirs` shows it
+pub fn abbreviate(dir: &str, shell: &Shell) -> String {
+    let home = shell.vars.get("HOME").unwrap_or_default();
+    match dir.strip_prefix(home) {
+        Some(rest) if !home.is_empty() && (rest.is_empty() || rest.starts_with('/')) => {
+            "~".to_string() + rest
+        }
+        _ => dir.to_string(),
+    }
+}
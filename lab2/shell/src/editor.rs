@@ -0,0 +1,590 @@
+use crate::complete::{Candidate, Completions};
+use crate::history::History;
+use crate::signals;
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::signal::Signal;
+use nix::sys::termios::{self, InputFlags, LocalFlags, SetArg, SpecialCharacterIndices, Termios};
+use nix::unistd::read;
+use std::io::{self, Write};
+use std::mem;
+
+/// a key read from the terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Key {
+    Char(char),
+    /// a control character, like Ctrl('a') for Ctrl-A
+    Ctrl(char),
+    /// a character with Alt or after Esc
+    Alt(char),
+    Enter,
+    /// Alt-Enter, inserts a line break
+    AltEnter,
+    Tab,
+    Backspace,
+    AltBackspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    CtrlLeft,
+    CtrlRight,
+    Unknown,
+}
+
+/// an interactive line editor with emacs keybindings
+#[derive(Default)]
+pub struct Editor {
+    buffer: Vec<char>,
+    /// index of the char under the cursor in the buffer
+    cursor: usize,
+    /// text removed by the last kill, inserted back by Ctrl-Y
+    killed: String,
+    /// terminal rows between the start of the prompt and the cursor, as drawn last
+    cursor_row: usize,
+}
+
+/// the terminal in raw mode, restored when dropped
+struct RawMode(Termios);
+
+impl RawMode {
+    fn enable() -> io::Result<RawMode> {
+        let original = termios::tcgetattr(0)?;
+        let mut raw = original.clone();
+        raw.local_flags
+            .remove(LocalFlags::ICANON | LocalFlags::ECHO | LocalFlags::ISIG | LocalFlags::IEXTEN);
+        raw.input_flags.remove(InputFlags::IXON | InputFlags::ICRNL);
+        raw.control_chars[SpecialCharacterIndices::VMIN as usize] = 1;
+        raw.control_chars[SpecialCharacterIndices::VTIME as usize] = 0;
+        termios::tcsetattr(0, SetArg::TCSADRAIN, &raw)?;
+        Ok(RawMode(original))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        termios::tcsetattr(0, SetArg::TCSADRAIN, &self.0).ok();
+    }
+}
+
+impl Editor {
+    /// read a line from the terminal after showing the prompt, None on EOF
+    /// and an `Interrupted` error on Ctrl-C
+    /// `complete` gives the completions for the text before the cursor on Tab
+    pub fn read_line(
+        &mut self,
+        prompt: &str,
+        history: &History,
+        complete: &dyn Fn(&str) -> Completions,
+    ) -> io::Result<Option<String>> {
+        let _raw = RawMode::enable()?;
+        self.buffer.clear();
+        self.cursor = 0;
+        self.cursor_row = 0;
+        // the history entry shown, as for `rget`, and the line edited before walking history
+        let mut history_index: Option<usize> = None;
+        let mut edited = Vec::new();
+        self.render(prompt)?;
+        loop {
+            let mut key = match read_key()? {
+                Some(key) => key,
+                None if self.buffer.is_empty() => return Ok(None),
+                None => Key::Enter,
+            };
+            if key == Key::Ctrl('r') {
+                key = match self.search(history)? {
+                    Some(key) => key,
+                    None => {
+                        self.render(prompt)?;
+                        continue;
+                    }
+                };
+            }
+            match key {
+                Key::Enter => {
+                    self.cursor = self.buffer.len();
+                    self.render(prompt)?;
+                    println!();
+                    return Ok(Some(self.buffer.iter().collect()));
+                }
+                Key::Ctrl('c') => {
+                    self.cursor = self.buffer.len();
+                    self.render(prompt)?;
+                    println!("^C");
+                    return Err(io::ErrorKind::Interrupted.into());
+                }
+                Key::Ctrl('d') if self.buffer.is_empty() => return Ok(None),
+                Key::Ctrl('d') | Key::Delete if self.cursor < self.buffer.len() => {
+                    self.buffer.remove(self.cursor);
+                }
+                Key::Backspace | Key::Ctrl('h') if self.cursor > 0 => {
+                    self.cursor -= 1;
+                    self.buffer.remove(self.cursor);
+                }
+                Key::Char(c) => self.insert(&[c]),
+                Key::Tab => {
+                    let before: String = self.buffer[..self.cursor].iter().collect();
+                    self.complete(prompt, complete(&before), &before)?;
+                }
+                Key::AltEnter => self.insert(&['\n']),
+                Key::Ctrl('a') | Key::Home => self.cursor = self.line_start(),
+                Key::Ctrl('e') | Key::End => self.cursor = self.line_end(),
+                Key::Ctrl('b') | Key::Left => self.cursor = self.cursor.saturating_sub(1),
+                Key::Ctrl('f') | Key::Right => {
+                    self.cursor = (self.cursor + 1).min(self.buffer.len())
+                }
+                Key::Alt('b') | Key::CtrlLeft => self.cursor = self.word_start(),
+                Key::Alt('f') | Key::CtrlRight => self.cursor = self.word_end(),
+                Key::Ctrl('k') => {
+                    let end = match self.line_end() {
+                        // at the end of a line, join the next one
+                        end if end == self.cursor => (end + 1).min(self.buffer.len()),
+                        end => end,
+                    };
+                    self.kill(self.cursor, end);
+                }
+                Key::Ctrl('u') => self.kill(self.line_start(), self.cursor),
+                Key::Ctrl('w') => {
+                    let mut start = self.cursor;
+                    while start > 0 && self.buffer[start - 1].is_whitespace() {
+                        start -= 1;
+                    }
+                    while start > 0 && !self.buffer[start - 1].is_whitespace() {
+                        start -= 1;
+                    }
+                    self.kill(start, self.cursor);
+                }
+                Key::AltBackspace => self.kill(self.word_start(), self.cursor),
+                Key::Alt('d') => self.kill(self.cursor, self.word_end()),
+                Key::Ctrl('y') => {
+                    let killed: Vec<char> = self.killed.chars().collect();
+                    self.insert(&killed);
+                }
+                // swap the chars around the cursor, moving it forward
+                Key::Ctrl('t') if self.cursor > 0 && self.buffer.len() > 1 => {
+                    if self.cursor == self.buffer.len() {
+                        self.cursor -= 1;
+                    }
+                    self.buffer.swap(self.cursor - 1, self.cursor);
+                    self.cursor += 1;
+                }
+                Key::Ctrl('l') => {
+                    print!("\x1b[H\x1b[2J");
+                    self.cursor_row = 0;
+                }
+                Key::Ctrl('p') | Key::Up => {
+                    let index = history_index.map_or(0, |index| index + 1);
+                    if let Some(line) = history.rget(index) {
+                        if history_index.is_none() {
+                            edited = mem::take(&mut self.buffer);
+                        }
+                        history_index = Some(index);
+                        self.set_buffer(line.chars().collect());
+                    }
+                }
+                Key::Ctrl('n') | Key::Down => match history_index {
+                    Some(0) => {
+                        history_index = None;
+                        self.set_buffer(mem::take(&mut edited));
+                    }
+                    Some(index) => {
+                        history_index = Some(index - 1);
+                        self.set_buffer(history.rget(index - 1).unwrap().chars().collect());
+                    }
+                    None => (),
+                },
+                _ => (),
+            }
+            self.render(prompt)?;
+        }
+    }
+
+    /// incremental reverse search of history, started by Ctrl-R
+    /// return the key that ended it with the match in the buffer, None if cancelled
+    fn search(&mut self, history: &History) -> io::Result<Option<Key>> {
+        let original = (self.buffer.clone(), self.cursor);
+        let mut query = String::new();
+        // index of the match, as for `rget`
+        let mut found: Option<usize> = None;
+        let mut failed = false;
+        loop {
+            let prompt = format!(
+                "({}reverse-i-search)`{}': ",
+                if failed { "failed " } else { "" },
+                query
+            );
+            self.render(&prompt)?;
+            let from = match read_key()? {
+                Some(Key::Ctrl('r')) => found.map_or(0, |index| index + 1),
+                Some(Key::Char(c)) => {
+                    query.push(c);
+                    found.unwrap_or(0)
+                }
+                Some(Key::Backspace | Key::Ctrl('h')) => {
+                    query.pop();
+                    0
+                }
+                Some(Key::Ctrl('g') | Key::Ctrl('c')) => {
+                    (self.buffer, self.cursor) = original;
+                    return Ok(None);
+                }
+                Some(key) => return Ok(Some(key)),
+                None => return Ok(Some(Key::Enter)),
+            };
+            if query.is_empty() {
+                continue;
+            }
+            let matched =
+                (from..history.size()).find(|&index| history.rget(index).unwrap().contains(&query));
+            failed = matched.is_none();
+            if let Some(index) = matched {
+                found = Some(index);
+                let line = history.rget(index).unwrap();
+                let position = line[..line.find(&query).unwrap()].chars().count();
+                self.buffer = line.chars().collect();
+                self.cursor = position;
+            }
+        }
+    }
+
+    /// replace the word before the cursor with the completions in common
+    /// list them all if that adds nothing
+    fn complete(&mut self, prompt: &str, completions: Completions, before: &str) -> io::Result<()> {
+        let candidates = &completions.candidates;
+        let word = &before[completions.start..];
+        let mut common = match candidates.first() {
+            Some(first) => first.text.clone(),
+            None => return Ok(()),
+        };
+        for candidate in &candidates[1..] {
+            let len = common
+                .char_indices()
+                .zip(candidate.text.chars())
+                .find(|((_, a), b)| a != b)
+                .map_or(common.len().min(candidate.text.len()), |((index, _), _)| {
+                    index
+                });
+            common.truncate(len);
+        }
+        if candidates.len() == 1 && !common.ends_with('/') {
+            common.push(' ');
+        }
+        if common.len() > word.len() || candidates.len() == 1 {
+            let start = before[..completions.start].chars().count();
+            self.buffer.drain(start..self.cursor);
+            self.cursor = start;
+            self.insert(&common.chars().collect::<Vec<_>>());
+            return Ok(());
+        }
+        self.list(prompt, candidates)
+    }
+
+    /// show candidates in columns below the line, then draw the line again
+    fn list(&mut self, prompt: &str, candidates: &[Candidate]) -> io::Result<()> {
+        let cursor = self.cursor;
+        self.cursor = self.buffer.len();
+        self.render(prompt)?;
+        self.cursor = cursor;
+        println!();
+        if candidates.len() > 100 {
+            print!("Display all {} possibilities? (y or n)", candidates.len());
+            io::stdout().flush()?;
+            let answer = read_key()?;
+            println!();
+            if answer != Some(Key::Char('y')) {
+                self.cursor_row = 0;
+                return Ok(());
+            }
+        }
+        let width = candidates
+            .iter()
+            .map(|candidate| text_width(&candidate.display))
+            .max()
+            .unwrap_or(0)
+            + 2;
+        let per_row = (terminal_width() / width).max(1);
+        let rows = candidates.len().div_ceil(per_row);
+        let mut output = String::new();
+        for row in 0..rows {
+            // down the columns, like `ls`
+            for candidate in candidates.iter().skip(row).step_by(rows) {
+                output += &candidate.display;
+                output += &" ".repeat(width - text_width(&candidate.display));
+            }
+            output = output.trim_end().to_string() + "\n";
+        }
+        print!("{}", output);
+        self.cursor_row = 0;
+        Ok(())
+    }
+
+    fn insert(&mut self, chars: &[char]) {
+        self.buffer
+            .splice(self.cursor..self.cursor, chars.iter().copied());
+        self.cursor += chars.len();
+    }
+
+    fn set_buffer(&mut self, buffer: Vec<char>) {
+        self.buffer = buffer;
+        self.cursor = self.buffer.len();
+    }
+
+    /// remove the text between two positions, keep it for Ctrl-Y
+    fn kill(&mut self, start: usize, end: usize) {
+        if start < end {
+            self.killed = self.buffer.drain(start..end).collect();
+            self.cursor = start;
+        }
+    }
+
+    /// start of the line the cursor is on, in a multi-line buffer
+    fn line_start(&self) -> usize {
+        self.buffer[..self.cursor]
+            .iter()
+            .rposition(|&c| c == '\n')
+            .map_or(0, |index| index + 1)
+    }
+
+    fn line_end(&self) -> usize {
+        self.buffer[self.cursor..]
+            .iter()
+            .position(|&c| c == '\n')
+            .map_or(self.buffer.len(), |index| self.cursor + index)
+    }
+
+    /// start of the word before the cursor
+    fn word_start(&self) -> usize {
+        let mut start = self.cursor;
+        while start > 0 && !self.buffer[start - 1].is_alphanumeric() {
+            start -= 1;
+        }
+        while start > 0 && self.buffer[start - 1].is_alphanumeric() {
+            start -= 1;
+        }
+        start
+    }
+
+    /// end of the word after the cursor
+    fn word_end(&self) -> usize {
+        let mut end = self.cursor;
+        while end < self.buffer.len() && !self.buffer[end].is_alphanumeric() {
+            end += 1;
+        }
+        while end < self.buffer.len() && self.buffer[end].is_alphanumeric() {
+            end += 1;
+        }
+        end
+    }
+
+    /// redraw the prompt and buffer in place, and put the cursor where it is in the buffer
+    fn render(&mut self, prompt: &str) -> io::Result<()> {
+        let columns = terminal_width();
+        let mut output = String::new();
+        if self.cursor_row > 0 {
+            output += &format!("\x1b[{}A", self.cursor_row);
+        }
+        output += "\r\x1b[J";
+        output += prompt;
+        output.extend(&self.buffer);
+
+        let start = advance((0, 0), strip_escapes(prompt).chars(), columns);
+        let mut end = advance(start, self.buffer.iter().copied(), columns);
+        if end.1 >= columns {
+            // the terminal waits for another char to wrap, make it move now
+            output.push('\n');
+            end = (end.0 + 1, 0);
+        }
+        let mut cursor = advance(start, self.buffer[..self.cursor].iter().copied(), columns);
+        if cursor.1 >= columns {
+            cursor = (cursor.0 + 1, 0);
+        }
+        if end.0 > cursor.0 {
+            output += &format!("\x1b[{}A", end.0 - cursor.0);
+        }
+        output.push('\r');
+        if cursor.1 > 0 {
+            output += &format!("\x1b[{}C", cursor.1);
+        }
+        self.cursor_row = cursor.0;
+
+        let mut stdout = io::stdout();
+        stdout.write_all(output.as_bytes())?;
+        stdout.flush()
+    }
+}
+
+/// read a key from stdin, None on EOF
+fn read_key() -> io::Result<Option<Key>> {
+    let byte = match read_byte()? {
+        Some(byte) => byte,
+        None => return Ok(None),
+    };
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        0x7f => Key::Backspace,
+        0x1b => read_escape()?,
+        0..=0x1f => Key::Ctrl((byte + b'a' - 1) as char),
+        _ => Key::Char(read_char(byte)?),
+    };
+    Ok(Some(key))
+}
+
+/// one byte from stdin, or a Ctrl-C if SIGINT is caught meanwhile
+/// other signals are left for the shell to act on after the line
+fn read_byte() -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    loop {
+        let mut fds = [
+            PollFd::new(0, PollFlags::POLLIN),
+            PollFd::new(signals::fd(), PollFlags::POLLIN),
+        ];
+        match poll(&mut fds, -1) {
+            Ok(_) => (),
+            Err(Errno::EINTR) => continue,
+            Err(err) => return Err(err.into()),
+        }
+        if fds[1].revents().is_some_and(|events| !events.is_empty()) {
+            signals::drain();
+            if signals::take_signal(Signal::SIGINT) {
+                return Ok(Some(0x03));
+            }
+        }
+        if fds[0].revents().is_some_and(|events| events.is_empty()) {
+            continue;
+        }
+        match read(0, &mut byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte[0])),
+            Err(Errno::EINTR | Errno::EAGAIN) => (),
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+/// the rest of a UTF-8 char whose first byte is read
+fn read_char(first: u8) -> io::Result<char> {
+    let len = match first {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => 1,
+    };
+    let mut bytes = vec![first];
+    for _ in 1..len {
+        bytes.extend(read_byte()?);
+    }
+    Ok(String::from_utf8_lossy(&bytes)
+        .chars()
+        .next()
+        .unwrap_or('\u{fffd}'))
+}
+
+/// the key of an escape sequence after Esc
+fn read_escape() -> io::Result<Key> {
+    let byte = match read_byte()? {
+        Some(byte) => byte,
+        None => return Ok(Key::Unknown),
+    };
+    if byte != b'[' && byte != b'O' {
+        return Ok(match byte {
+            b'\r' | b'\n' => Key::AltEnter,
+            0x7f | 0x08 => Key::AltBackspace,
+            _ => Key::Alt((byte as char).to_ascii_lowercase()),
+        });
+    }
+    // a control sequence: parameters then a final byte
+    let mut sequence = String::new();
+    loop {
+        match read_byte()? {
+            Some(byte @ 0x40..=0x7e) => {
+                sequence.push(byte as char);
+                break;
+            }
+            Some(byte) => sequence.push(byte as char),
+            None => break,
+        }
+    }
+    Ok(match sequence.as_str() {
+        "A" => Key::Up,
+        "B" => Key::Down,
+        "C" => Key::Right,
+        "D" => Key::Left,
+        "H" | "1~" | "7~" => Key::Home,
+        "F" | "4~" | "8~" => Key::End,
+        "3~" => Key::Delete,
+        "1;5C" | "1;3C" => Key::CtrlRight,
+        "1;5D" | "1;3D" => Key::CtrlLeft,
+        _ => Key::Unknown,
+    })
+}
+
+/// the (row, column) after drawing chars from a position, on a terminal that wraps
+fn advance(
+    (mut row, mut column): (usize, usize),
+    chars: impl Iterator<Item = char>,
+    columns: usize,
+) -> (usize, usize) {
+    for c in chars {
+        if c == '\n' {
+            row += 1;
+            column = 0;
+            continue;
+        }
+        let width = char_width(c);
+        if column + width > columns {
+            row += 1;
+            column = 0;
+        }
+        column += width;
+    }
+    (row, column)
+}
+
+/// columns taken by a char on the terminal, 2 for East Asian wide chars
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        0..=0x1f | 0x7f => 0,
+        0x1100..=0x115f
+        | 0x2e80..=0xa4cf
+        | 0xac00..=0xd7a3
+        | 0xf900..=0xfaff
+        | 0xfe30..=0xfe4f
+        | 0xff00..=0xff60
+        | 0xffe0..=0xffe6
+        | 0x1f300..=0x1f64f
+        | 0x1f900..=0x1f9ff
+        | 0x20000..=0x3fffd => 2,
+        _ => 1,
+    }
+}
+
+fn text_width(text: &str) -> usize {
+    text.chars().map(char_width).sum()
+}
+
+/// text without the color escape sequences, as it shows on the terminal
+fn strip_escapes(text: &str) -> String {
+    let mut result = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            chars.by_ref().find(|c| c.is_ascii_alphabetic());
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// width of the terminal, 80 if unknown
+fn terminal_width() -> usize {
+    let mut size: libc::winsize = unsafe { mem::zeroed() };
+    match unsafe { libc::ioctl(1, libc::TIOCGWINSZ, &mut size) } {
+        0 if size.ws_col > 0 => size.ws_col as usize,
+        _ => 80,
+    }
+}
@@ -0,0 +1,1154 @@
+use crate::builtin::{do_built_in, is_built_in, runs_commands, strerror, Output};
+use crate::expand;
+use crate::glob;
+use crate::jobs::{give_terminal, prepare_child, Job, JobState};
+use crate::lexer::{self, Op};
+use crate::parser::{
+    self, AndOr, Case, Connector, For, If, List, Loop, Pipeline, Redirect, RedirectTarget,
+    SimpleCommand,
+};
+use crate::redirect::{self, open_file, text_file, FdTable};
+use crate::resolve;
+use crate::shell::{Control, Shell};
+use crate::signals;
+use crate::vars::{quote, split_assignment};
+use nix::fcntl::OFlag;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::{fork, getpgrp, pipe2, setpgid, ForkResult, Pid};
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::Command;
+
+/// how a command of a pipeline has been started
+enum Started {
+    /// a child process is running it
+    Child(Pid),
+    /// it has already finished in the shell with the status
+    Finished(i32),
+    /// no process could be made for it, as `fork` failed, with the status
+    Failed(i32),
+}
+
+/// where a new child goes under job control
+#[derive(Clone, Copy)]
+struct ChildGroup {
+    /// process group to join, 0 to lead a new one
+    pgid: Pid,
+    /// the terminal to take, for a foreground job
+    tty: Option<RawFd>,
+}
+
+/// act on the signals caught since the last time: run the traps set for them,
+/// and leave the commands being run on SIGINT without a trap
+pub fn handle_signals(shell: &mut Shell) {
+    for signal in signals::take() {
+        match shell.traps.get(&(signal as i32)) {
+            Some(action) => {
+                let action = action.clone();
+                run_trap(&action, shell);
+            }
+            None if signal == Signal::SIGINT => {
+                println!();
+                shell.control = Some(Control::Interrupt);
+            }
+            None => (),
+        }
+    }
+}
+
+/// run the action of a trap, which leaves `$?` as it is
+pub fn run_trap(action: &str, shell: &mut Shell) {
+    let status = shell.status;
+    let control = shell.control.take();
+    run_source(action, shell);
+    shell.status = status;
+    shell.control = control;
+}
+
+/// leave the shell with the status, after running the `EXIT` trap
+pub fn exit_shell(status: i32, shell: &mut Shell) -> ! {
+    if let Some(action) = shell.traps.remove(&0) {
+        shell.status = status;
+        run_trap(&action, shell);
+    }
+    io::stdout().flush().ok();
+    std::process::exit(status)
+}
+
+/// parse and run commands from source text a line at a time, return the status
+/// of the last one. a syntax error stops it
+pub fn run_source(source: &str, shell: &mut Shell) -> i32 {
+    let mut lines = match lexer::tokenize(source) {
+        Ok(tokens) => parser::Lines::new(tokens),
+        Err(err) => return syntax_error(&format!("syntax error: {}", err), shell),
+    };
+    let mut status = shell.status;
+    while let Some(line) = lines.next_line(&shell.aliases) {
+        status = match line {
+            Ok(list) => run_list(&list, shell),
+            Err(err) => return syntax_error(&err.to_string(), shell),
+        };
+        if shell.control.is_some() {
+            break;
+        }
+    }
+    status
+}
+
+fn syntax_error(err: &str, shell: &mut Shell) -> i32 {
+    eprintln!("llysh: {}", err);
+    shell.status = 2;
+    2
+}
+
+/// run the commands of a file in the shell, like with `source`, where `return`
+/// leaves the file. args replace the positional parameters if any
+pub fn run_file(source: &str, args: &[String], shell: &mut Shell) -> i32 {
+    let saved_args = match args.is_empty() {
+        true => None,
+        false => {
+            let args = shell.args[..1].iter().chain(args).cloned().collect();
+            Some(std::mem::replace(&mut shell.args, args))
+        }
+    };
+    shell.call_depth += 1;
+    let status = run_source(source, shell);
+    shell.call_depth -= 1;
+    if let Some(args) = saved_args {
+        shell.args = args;
+    }
+    if shell.control == Some(Control::Return) {
+        shell.control = None;
+    }
+    status
+}
+
+/// whether source text ends in the middle of a command, like in quotes or in
+/// `if` without `fi`, so that more lines are needed
+pub fn is_incomplete(source: &str, shell: &Shell) -> bool {
+    parser::is_incomplete(source, &shell.aliases)
+}
+
+/// run and-or lists one by one, return the status of the last one
+/// stop early when a built-in like `return` asks to
+pub fn run_list(list: &List, shell: &mut Shell) -> i32 {
+    let mut status = shell.status;
+    for and_or in &list.0 {
+        // `set -n` is for checking scripts, a user could not turn it off
+        if shell.options.noexec && !shell.interactive {
+            break;
+        }
+        status = run_and_or(and_or, shell);
+        handle_signals(shell);
+        if shell.control.is_some() {
+            break;
+        }
+    }
+    status
+}
+
+/// run pipelines, skip those whose connector does not match the last status
+fn run_and_or(and_or: &AndOr, shell: &mut Shell) -> i32 {
+    if and_or.background {
+        return run_background(and_or, shell);
+    }
+    // the pipelines before the last one are conditions
+    let mut ran_last = and_or.rest.is_empty();
+    let mut status = as_condition(!ran_last, shell, |shell| {
+        run_pipeline(&and_or.first, shell, true)
+    });
+    for (i, (connector, pipeline)) in and_or.rest.iter().enumerate() {
+        if shell.control.is_some() {
+            break;
+        }
+        if (*connector == Connector::And) == (status == 0) {
+            ran_last = i + 1 == and_or.rest.len();
+            status = as_condition(!ran_last, shell, |shell| {
+                run_pipeline(pipeline, shell, true)
+            });
+        }
+    }
+    if ran_last && status != 0 {
+        check_errexit(status, shell);
+    }
+    status
+}
+
+/// run `f` as a condition if `condition`, where a failure does not leave the
+/// shell for `set -e`
+fn as_condition(condition: bool, shell: &mut Shell, f: impl FnOnce(&mut Shell) -> i32) -> i32 {
+    shell.conditions += condition as usize;
+    let status = f(shell);
+    shell.conditions -= condition as usize;
+    status
+}
+
+/// for `set -e`, leave the shell after a command failing outside of conditions
+fn check_errexit(status: i32, shell: &mut Shell) {
+    if shell.options.errexit && shell.conditions == 0 && shell.control.is_none() {
+        exit_shell(status, shell);
+    }
+}
+
+/// start an and-or list as a background job
+/// a single pipeline is started directly, otherwise a child shell runs the list
+fn run_background(and_or: &AndOr, shell: &mut Shell) -> i32 {
+    if and_or.rest.is_empty() {
+        return run_pipeline(&and_or.first, shell, false);
+    }
+    let group = shell.tty.map(|_| ChildGroup {
+        pgid: Pid::from_raw(0),
+        tty: None,
+    });
+    let foreground = AndOr {
+        background: false,
+        ..and_or.clone()
+    };
+    match fork_child(shell, group, |shell| run_and_or(&foreground, shell)) {
+        Started::Child(pid) => {
+            let pgid = group.map(|_| pid);
+            add_background_job(Job::new(pgid, foreground.to_string(), vec![pid]), shell);
+            0
+        }
+        Started::Finished(status) | Started::Failed(status) => status,
+    }
+}
+
+/// put a job started in the background to the job table
+fn add_background_job(job: Job, shell: &mut Shell) {
+    let pid = job.processes.last().map(|p| p.pid);
+    let id = shell.jobs.add(job);
+    if let (Some(_), Some(pid)) = (shell.tty, pid) {
+        println!("[{}] {}", id, pid);
+    }
+}
+
+/// run commands and concat their stdios with pipes
+/// return the status of the last command, and record all of them in the shell
+fn run_pipeline(pipeline: &Pipeline, shell: &mut Shell, foreground: bool) -> i32 {
+    // a lone compound command other than a subshell runs in the current shell
+    if let [command] = pipeline.commands.as_slice() {
+        if foreground && is_compound(command) {
+            shell.status = run_in_shell(command, shell);
+            return shell.status;
+        }
+    }
+
+    // how every command has been started, in order
+    let mut stages = Vec::new();
+    let mut pgid = None;
+    let mut child_stdin = None;
+    // built-ins run in a child shell unless they are the whole foreground pipeline
+    let fork_built_in = pipeline.commands.len() > 1 || !foreground;
+    let mut command_iter = pipeline.commands.iter().peekable();
+    while let Some(command) = command_iter.next() {
+        let last = command_iter.peek().is_none();
+        let (child_stdout, next_stdin) = if last {
+            (None, None)
+        } else {
+            match make_pipe() {
+                Some((read, write)) => (Some(write), Some(read)),
+                None => {
+                    eprintln!("llysh: cannot create pipe");
+                    stages.push(Started::Failed(1));
+                    abort_stages(&stages);
+                    break;
+                }
+            }
+        };
+        let group = shell.tty.map(|tty| ChildGroup {
+            pgid: pgid.unwrap_or_else(|| Pid::from_raw(0)),
+            tty: foreground.then_some(tty),
+        });
+        let started = execute_command(
+            command,
+            shell,
+            child_stdin,
+            child_stdout,
+            group,
+            fork_built_in,
+        );
+        if let Started::Child(pid) = started {
+            if group.is_some() {
+                // also in the parent, so the group exists before the next child joins
+                setpgid(pid, pgid.unwrap_or(pid)).ok();
+            }
+            pgid.get_or_insert(pid);
+        }
+        // one that finished, even with an error like 127, has closed its end of
+        // the pipe, and the next one reads nothing from it
+        let failed = matches!(started, Started::Failed(_));
+        stages.push(started);
+        if failed && !last {
+            // the pipe it was to read from is closed already, and what it was
+            // to write to is closed when `next_stdin` is dropped here
+            abort_stages(&stages);
+            break;
+        }
+        child_stdin = next_stdin;
+    }
+
+    let pids = stages.iter().filter_map(|stage| match stage {
+        Started::Child(pid) => Some(*pid),
+        Started::Finished(_) | Started::Failed(_) => None,
+    });
+    let job = Job::new(shell.tty.and(pgid), pipeline.to_string(), pids.collect());
+    let mut job_statuses = if job.processes.is_empty() {
+        Vec::new()
+    } else if foreground {
+        wait_foreground(job, shell)
+    } else {
+        add_background_job(job, shell);
+        return 0;
+    }
+    .into_iter();
+    // in the order of the commands. a stopped job has only one status
+    let statuses: Vec<i32> = stages
+        .iter()
+        .filter_map(|stage| match stage {
+            Started::Child(_) => job_statuses.next(),
+            Started::Finished(status) | Started::Failed(status) => Some(*status),
+        })
+        .collect();
+    shell.status = match shell.options.pipefail {
+        true => statuses.iter().rev().find(|&&status| status != 0),
+        false => statuses.last(),
+    }
+    .copied()
+    .unwrap_or(0);
+    shell.pipestatus = statuses;
+    shell.status
+}
+
+/// whether a command is a group, `if`, a loop, `case` or `(( ))`, which can run in the shell
+fn is_compound(command: &parser::Command) -> bool {
+    matches!(
+        command,
+        parser::Command::Group(..)
+            | parser::Command::If(..)
+            | parser::Command::Loop(..)
+            | parser::Command::For(..)
+            | parser::Command::Case(..)
+            | parser::Command::Arith(..)
+    )
+}
+
+/// run a compound command in the current shell, its redirections applied to
+/// the shell itself until it ends
+fn run_in_shell(command: &parser::Command, shell: &mut Shell) -> i32 {
+    let substitutions = shell.substitutions.len();
+    let status = run_in_shell_fds(command, shell);
+    shell.substitutions.truncate(substitutions);
+    status
+}
+
+/// `run_in_shell` before the process substitutions are closed
+fn run_in_shell_fds(command: &parser::Command, shell: &mut Shell) -> i32 {
+    if command.redirects().is_empty() {
+        return run_compound(command, shell);
+    }
+    let mut fds = match FdTable::new(None, None) {
+        Ok(fds) => fds,
+        Err(err) => {
+            eprintln!("llysh: {}", strerror(&err));
+            return 1;
+        }
+    };
+    if let Err(err) = apply_redirects(command.redirects(), shell, &mut fds) {
+        eprintln!("llysh: {}", err);
+        return 1;
+    }
+    with_fds(&fds, shell, |shell| run_compound(command, shell))
+}
+
+/// run the body of a compound command, without its redirections
+fn run_compound(command: &parser::Command, shell: &mut Shell) -> i32 {
+    match command {
+        parser::Command::Subshell(list, _) | parser::Command::Group(list, _) => {
+            run_list(list, shell)
+        }
+        parser::Command::If(clause, _) => run_if(clause, shell),
+        parser::Command::Loop(clause, _) => run_loop(clause, shell),
+        parser::Command::For(clause, _) => run_for(clause, shell),
+        parser::Command::Case(clause, _) => run_case(clause, shell),
+        parser::Command::Arith(expr, _) => match expand::expand_arith(expr, shell) {
+            Ok(value) => (value == 0) as i32,
+            Err(err) => {
+                eprintln!("llysh: {}", err);
+                1
+            }
+        },
+        parser::Command::Simple(_) | parser::Command::Function(..) => unreachable!(),
+    }
+}
+
+/// run the list of the first branch whose condition succeeds, 0 if none does
+fn run_if(clause: &If, shell: &mut Shell) -> i32 {
+    for (condition, body) in &clause.branches {
+        let status = as_condition(true, shell, |shell| run_list(condition, shell));
+        if shell.control.is_some() {
+            return status;
+        }
+        if status == 0 {
+            return run_list(body, shell);
+        }
+    }
+    match &clause.otherwise {
+        Some(otherwise) => run_list(otherwise, shell),
+        None => 0,
+    }
+}
+
+/// run `while` or `until`, return the status of the last run of the body
+fn run_loop(clause: &Loop, shell: &mut Shell) -> i32 {
+    let mut status = 0;
+    shell.loop_depth += 1;
+    loop {
+        let condition = as_condition(true, shell, |shell| run_list(&clause.condition, shell));
+        if !next_iteration(shell) || (condition == 0) == clause.until {
+            break;
+        }
+        status = run_list(&clause.body, shell);
+        if !next_iteration(shell) {
+            break;
+        }
+    }
+    shell.loop_depth -= 1;
+    status
+}
+
+/// run the body of `for` with the variable set to each word
+fn run_for(clause: &For, shell: &mut Shell) -> i32 {
+    let words = match &clause.words {
+        Some(words) => match get_tokens(words, shell).map_err(|err| expansion_error(err, shell)) {
+            Ok(words) => words,
+            Err(err) => {
+                eprintln!("llysh: {}", err);
+                return 1;
+            }
+        },
+        None => shell.args[1..].to_vec(),
+    };
+    let mut status = 0;
+    shell.loop_depth += 1;
+    for word in words {
+        if let Err(err) = shell.vars.set(&clause.name, &word) {
+            eprintln!("llysh: {}", err);
+            status = 1;
+            break;
+        }
+        status = run_list(&clause.body, shell);
+        if !next_iteration(shell) {
+            break;
+        }
+    }
+    shell.loop_depth -= 1;
+    status
+}
+
+/// take a `break` or `continue` meant for the loop being run
+/// return whether the loop goes on
+fn next_iteration(shell: &mut Shell) -> bool {
+    match shell.control {
+        None => true,
+        Some(Control::Break(n)) => {
+            shell.control = (n > 1).then_some(Control::Break(n - 1));
+            false
+        }
+        Some(Control::Continue(n)) if n > 1 => {
+            shell.control = Some(Control::Continue(n - 1));
+            false
+        }
+        Some(Control::Continue(_)) => {
+            shell.control = None;
+            true
+        }
+        Some(Control::Return | Control::Interrupt) => false,
+    }
+}
+
+/// run the list of the first item with a pattern matching the word, 0 if none
+fn run_case(clause: &Case, shell: &mut Shell) -> i32 {
+    let word = match expand::expand_string(&clause.word, shell)
+        .map_err(|err| expansion_error(err, shell))
+    {
+        Ok(word) => word,
+        Err(err) => {
+            eprintln!("llysh: {}", err);
+            return 1;
+        }
+    };
+    for item in &clause.items {
+        for pattern in &item.patterns {
+            match expand::expand_pattern(pattern, shell).map_err(|err| expansion_error(err, shell))
+            {
+                Ok(pattern) if glob::matches(&pattern, &word) => {
+                    return run_list(&item.body, shell);
+                }
+                Ok(_) => (),
+                Err(err) => {
+                    eprintln!("llysh: {}", err);
+                    return 1;
+                }
+            }
+        }
+    }
+    0
+}
+
+/// run `f` with the fds of the shell itself set up like a command's
+fn with_fds(fds: &FdTable, shell: &mut Shell, f: impl FnOnce(&mut Shell) -> i32) -> i32 {
+    io::stdout().flush().ok();
+    let saved = match redirect::install_saved(&fds.plan()) {
+        Ok(saved) => saved,
+        Err(err) => {
+            eprintln!("llysh: {}", strerror(&err));
+            return 1;
+        }
+    };
+    let status = f(shell);
+    io::stdout().flush().ok();
+    redirect::restore(saved);
+    status
+}
+
+/// run a function with its arguments as the positional parameters
+fn call_function(body: &parser::Command, args: Vec<String>, shell: &mut Shell) -> i32 {
+    let mut args = args;
+    args.insert(0, shell.args[0].clone());
+    let saved_args = std::mem::replace(&mut shell.args, args);
+    shell.vars.push_frame();
+    shell.call_depth += 1;
+    let pipeline = Pipeline {
+        commands: vec![body.clone()],
+    };
+    let status = run_pipeline(&pipeline, shell, true);
+    shell.call_depth -= 1;
+    shell.vars.pop_frame();
+    shell.args = saved_args;
+    if shell.control == Some(Control::Return) {
+        shell.control = None;
+    }
+    status
+}
+
+/// stop the commands of a pipeline started before one that could not be,
+/// as if what they write went nowhere, which it does
+fn abort_stages(stages: &[Started]) {
+    for stage in stages {
+        if let Started::Child(pid) = stage {
+            kill(*pid, Signal::SIGPIPE).ok();
+        }
+    }
+}
+
+/// wait for a job in the foreground, return the statuses of its processes
+/// a stopped job is put to the job table
+pub fn wait_foreground(mut job: Job, shell: &mut Shell) -> Vec<i32> {
+    if let (Some(tty), Some(pgid)) = (shell.tty, job.pgid) {
+        give_terminal(tty, pgid);
+    }
+    job.wait();
+    if let Some(tty) = shell.tty {
+        give_terminal(tty, getpgrp());
+    }
+    if job.state() != JobState::Stopped {
+        // like the shell got it too, leave the commands being run
+        if job
+            .processes
+            .iter()
+            .any(|p| p.signal == Some(Signal::SIGINT))
+        {
+            // the line after `^C`, and the shell is not interrupted again
+            println!();
+            signals::take_signal(Signal::SIGINT);
+            shell.control = Some(Control::Interrupt);
+        }
+        // a broken pipe is how a pipeline usually ends, no news
+        let terminated = job
+            .processes
+            .iter()
+            .rev()
+            .find(|p| !matches!(p.signal, None | Some(Signal::SIGINT | Signal::SIGPIPE)));
+        if let Some(termination) = terminated.and_then(|p| p.termination()) {
+            eprintln!("llysh: {}: {}", job.command, termination);
+        }
+        return job.statuses();
+    }
+    let id = shell.jobs.add(job);
+    if let Some(job) = shell.jobs.get_mut(id) {
+        println!();
+        println!("{}", job.describe('+'));
+    }
+    vec![128 + Signal::SIGTSTP as i32]
+}
+
+/// create a pipe, return its read and write ends
+fn make_pipe() -> Option<(File, File)> {
+    let (read, write) = pipe2(OFlag::O_CLOEXEC).ok()?;
+    Some(unsafe { (File::from_raw_fd(read), File::from_raw_fd(write)) })
+}
+
+/// execute one command of a pipeline
+/// stdin and stdout are suggested by pipe (None to inherit). redirections are prior
+fn execute_command(
+    command: &parser::Command,
+    shell: &mut Shell,
+    stdin: Option<File>,
+    stdout: Option<File>,
+    group: Option<ChildGroup>,
+    fork_built_in: bool,
+) -> Started {
+    if let parser::Command::Function(name, body) = command {
+        shell.functions.insert(name.clone(), *body.clone());
+        return Started::Finished(0);
+    }
+    // process substitutions of the command are closed once it has started
+    let substitutions = shell.substitutions.len();
+    let started = start_command(command, shell, stdin, stdout, group, fork_built_in);
+    shell.substitutions.truncate(substitutions);
+    started
+}
+
+/// `execute_command` before the process substitutions are closed
+fn start_command(
+    command: &parser::Command,
+    shell: &mut Shell,
+    stdin: Option<File>,
+    stdout: Option<File>,
+    group: Option<ChildGroup>,
+    fork_built_in: bool,
+) -> Started {
+    let mut fds = match FdTable::new(stdin, stdout) {
+        Ok(fds) => fds,
+        Err(err) => {
+            eprintln!("llysh: {}", strerror(&err));
+            return Started::Finished(1);
+        }
+    };
+    if let Err(err) = apply_redirects(command.redirects(), shell, &mut fds) {
+        eprintln!("llysh: {}", err);
+        return Started::Finished(1);
+    }
+    match command {
+        parser::Command::Simple(command) => {
+            execute_simple(command, shell, fds, group, fork_built_in)
+        }
+        _ => fork_child(shell, group, |shell| match redirect::install(&fds.plan()) {
+            Ok(()) => run_compound(command, shell),
+            Err(err) => {
+                eprintln!("llysh: {}", strerror(&err));
+                1
+            }
+        }),
+    }
+}
+
+/// execute a simple command, with its fds set up by pipes and redirections
+fn execute_simple(
+    command: &SimpleCommand,
+    shell: &mut Shell,
+    mut fds: FdTable,
+    group: Option<ChildGroup>,
+    fork_built_in: bool,
+) -> Started {
+    let expanded = expand_assignments(&command.assignments, shell)
+        .and_then(|assignments| Ok((assignments, get_tokens(&command.words, shell)?)))
+        .map_err(|err| expansion_error(err, shell));
+    let (assignments, words) = match expanded {
+        Ok(expanded) => expanded,
+        Err(err) => {
+            eprintln!("llysh: {}", err);
+            return Started::Finished(1);
+        }
+    };
+    // the `/dev/fd/N` of process substitutions are the same fds in the command
+    for file in &shell.substitutions {
+        if let Err(err) = file
+            .try_clone()
+            .and_then(|copy| fds.set(file.as_raw_fd(), Some(copy)))
+        {
+            eprintln!("llysh: {}", strerror(&err));
+            return Started::Finished(1);
+        }
+    }
+    if shell.options.xtrace {
+        let assigned = assignments
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, quote(value)));
+        trace(assigned.chain(words.iter().map(|word| quote(word))), shell);
+    }
+    let (words, skip_functions, default_path) = strip_command(words);
+    let mut word_iter = words.into_iter();
+    let prog = word_iter.next().unwrap_or_default();
+    let args: Vec<String> = word_iter.collect();
+    if prog.is_empty() {
+        // assignments alone set shell variables
+        for (name, value) in &assignments {
+            if let Err(err) = shell.vars.set(name, value) {
+                eprintln!("llysh: {}", err);
+                return Started::Finished(1);
+            }
+        }
+        // the status of the last command substitution, if any
+        let substituted = command
+            .assignments
+            .iter()
+            .any(|assignment| assignment.contains("$(") || assignment.contains('`'));
+        return Started::Finished(if substituted { shell.status } else { 0 });
+    }
+    if let Some(body) = shell
+        .functions
+        .get(&prog)
+        .filter(|_| !skip_functions)
+        .cloned()
+    {
+        let run = |shell: &mut Shell| {
+            with_fds(&fds, shell, |shell| {
+                with_assignments(&assignments, shell, |shell| {
+                    call_function(&body, args, shell)
+                })
+            })
+        };
+        return match fork_built_in {
+            true => fork_child(shell, group, run),
+            false => Started::Finished(run(shell)),
+        };
+    }
+    if is_built_in(&prog) {
+        let run = |shell: &mut Shell| {
+            with_assignments(&assignments, shell, |shell| match runs_commands(&prog) {
+                true => with_fds(&fds, shell, |shell| {
+                    do_built_in(&prog, &args, shell, &mut Output::new(&fds))
+                }),
+                false => do_built_in(&prog, &args, shell, &mut Output::new(&fds)),
+            })
+        };
+        return match fork_built_in {
+            true => fork_child(shell, group, run),
+            false => Started::Finished(run(shell)),
+        };
+    }
+    // `PATH=dirs command` is looked up there, and not remembered
+    let temporary_path = match default_path {
+        true => Some(resolve::DEFAULT_PATH),
+        false => assignments
+            .iter()
+            .find(|(name, _)| name == "PATH")
+            .map(|(_, value)| value.as_str()),
+    };
+    let path = match temporary_path {
+        Some(path) => path.to_string(),
+        None => shell.vars.get("PATH").unwrap_or_default().to_string(),
+    };
+    let file = match temporary_path {
+        Some(_) => resolve::search(&prog, &path).into_iter().next(),
+        None => shell.hash.lookup(&prog, &path),
+    };
+    // a file that is not a program is tried anyway, to tell why it cannot be run
+    let file = match file.or_else(|| resolve::search_file(&prog, &path)) {
+        Some(file) => file,
+        None => {
+            eprintln!("llysh: {}: command not found", prog);
+            return Started::Finished(127);
+        }
+    };
+    // run with `execve` here rather than the `execvp` of `Command`, which
+    // gives a file the kernel cannot execute to `/bin/sh` and so hides why
+    let mut env: BTreeMap<&String, &String> = shell.vars.exported().collect();
+    env.extend(assignments.iter().map(|(name, value)| (name, value)));
+    let env: Vec<String> = env
+        .into_iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    let argv = [&prog].into_iter().chain(&args);
+    let (path, argv, envp) = match (
+        CString::new(file.as_str()),
+        argv.map(|arg| CString::new(arg.as_str()))
+            .collect::<Result<Vec<_>, _>>(),
+        env.into_iter()
+            .map(CString::new)
+            .collect::<Result<Vec<_>, _>>(),
+    ) {
+        (Ok(path), Ok(argv), Ok(envp)) => (path, argv, envp),
+        _ => {
+            eprintln!("llysh: {}: argument with a null byte", prog);
+            return Started::Finished(126);
+        }
+    };
+    let mut child = Command::new(&file);
+    let plan = fds.plan();
+    unsafe {
+        child.pre_exec(move || redirect::install(&plan));
+        if let Some(group) = group {
+            child.pre_exec(move || {
+                prepare_child(group.pgid, group.tty);
+                Ok(())
+            });
+        }
+        child.pre_exec(|| {
+            signals::reset_child();
+            Ok(())
+        });
+        child.pre_exec(move || Err(execve(&path, &argv, &envp)));
+    }
+    let err = match child.spawn() {
+        Ok(child) => return Started::Child(Pid::from_raw(child.id() as i32)),
+        Err(err) => err,
+    };
+    let (message, status) = match err.raw_os_error() {
+        _ if Path::new(&file).is_dir() => ("Is a directory".to_string(), 126),
+        Some(libc::ENOENT) => (strerror(&err), 127),
+        Some(libc::ENOEXEC) => match fs::read(&file).map(|text| is_binary(&text)) {
+            Ok(false) => return run_as_script(&file, &prog, args, assignments, fds, group, shell),
+            _ => (
+                format!("cannot execute binary file: {}", strerror(&err)),
+                126,
+            ),
+        },
+        // `fork` itself failed
+        Some(libc::EAGAIN | libc::ENOMEM) => {
+            eprintln!("llysh: cannot fork: {}", strerror(&err));
+            return Started::Failed(1);
+        }
+        _ => (strerror(&err), 126),
+    };
+    eprintln!("llysh: {}: {}", prog, message);
+    Started::Finished(status)
+}
+
+/// `execve` a program, return the error when it cannot be
+fn execve(path: &CStr, argv: &[CString], envp: &[CString]) -> io::Error {
+    let pointers = |strings: &[CString]| {
+        let mut pointers: Vec<_> = strings.iter().map(|string| string.as_ptr()).collect();
+        pointers.push(std::ptr::null());
+        pointers
+    };
+    let (argv, envp) = (pointers(argv), pointers(envp));
+    unsafe { libc::execve(path.as_ptr(), argv.as_ptr(), envp.as_ptr()) };
+    io::Error::last_os_error()
+}
+
+/// whether the file the kernel does not know is a binary, an ELF one or with
+/// a null byte in its first line, or a script of shell commands
+fn is_binary(text: &[u8]) -> bool {
+    text.starts_with(b"\x7fELF")
+        || text
+            .iter()
+            .take(80)
+            .take_while(|&&byte| byte != b'\n')
+            .any(|&byte| byte == 0)
+}
+
+/// run a file without `#!` with the commands in a child shell, with `$0` its
+/// name, like a program
+fn run_as_script(
+    file: &str,
+    prog: &str,
+    args: Vec<String>,
+    assignments: Vec<(String, String)>,
+    fds: FdTable,
+    group: Option<ChildGroup>,
+    shell: &mut Shell,
+) -> Started {
+    let source = match fs::read_to_string(file) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("llysh: {}: {}", prog, strerror(&err));
+            return Started::Finished(126);
+        }
+    };
+    fork_child(shell, group, |shell| {
+        shell.args = [prog.to_string()].into_iter().chain(args).collect();
+        shell.functions.clear();
+        with_fds(&fds, shell, |shell| {
+            with_assignments(&assignments, shell, |shell| run_source(&source, shell))
+        })
+    })
+}
+
+/// the words of `command [-p] [--] name args...` without `command`, whether
+/// it was there, to skip functions, and whether `-p` was given, to search the
+/// default `$PATH`. `command -v` and such are left to the built-in
+fn strip_command(mut words: Vec<String>) -> (Vec<String>, bool, bool) {
+    let (mut stripped, mut default_path) = (false, false);
+    while words.first().is_some_and(|word| word == "command") {
+        let mut skip = 1;
+        let mut path = false;
+        while let Some(option) = words.get(skip).filter(|word| word.starts_with('-')) {
+            skip += 1;
+            match option.as_str() {
+                "--" => break,
+                _ if option.len() > 1 && option[1..].chars().all(|c| c == 'p') => path = true,
+                _ => return (words, stripped, default_path),
+            }
+        }
+        if skip == words.len() {
+            break;
+        }
+        words.drain(..skip);
+        stripped = true;
+        default_path |= path;
+    }
+    (words, stripped, default_path)
+}
+
+/// print a command as it is run after `$PS4`, for `set -x`
+fn trace(words: impl Iterator<Item = String>, shell: &mut Shell) {
+    let ps4 = shell.vars.get("PS4").unwrap_or_default().to_string();
+    // commands run to expand `$PS4` are not traced in turn
+    shell.options.xtrace = false;
+    let prefix = expand::expand_string(&ps4, shell).unwrap_or(ps4);
+    shell.options.xtrace = true;
+    eprintln!("{}{}", prefix, words.collect::<Vec<_>>().join(" "));
+}
+
+/// expand the values of `NAME=value` assignments, return (name, value) pairs
+fn expand_assignments(
+    assignments: &[String],
+    shell: &mut Shell,
+) -> Result<Vec<(String, String)>, String> {
+    let mut expanded = Vec::new();
+    for assignment in assignments {
+        let (name, value) = split_assignment(assignment).unwrap();
+        expanded.push((name.to_string(), expand::expand_string(value, shell)?));
+    }
+    Ok(expanded)
+}
+
+/// run `f` with variables exported for it only, like `NAME=value builtin`
+fn with_assignments(
+    assignments: &[(String, String)],
+    shell: &mut Shell,
+    f: impl FnOnce(&mut Shell) -> i32,
+) -> i32 {
+    let mut saved = Vec::new();
+    let mut result = Ok(());
+    for (name, value) in assignments {
+        saved.push((name, shell.vars.get_var(name).cloned()));
+        result = shell.vars.set(name, value);
+        if result.is_err() {
+            break;
+        }
+        shell.vars.export(name, true);
+    }
+    let status = match result {
+        Ok(()) => f(shell),
+        Err(err) => {
+            eprintln!("llysh: {}", err);
+            1
+        }
+    };
+    for (name, var) in saved.into_iter().rev() {
+        shell.vars.restore(name, var);
+    }
+    status
+}
+
+/// run `f` in a forked child shell, which exits with the status it returns
+fn fork_child(
+    shell: &mut Shell,
+    group: Option<ChildGroup>,
+    f: impl FnOnce(&mut Shell) -> i32,
+) -> Started {
+    match unsafe { fork() } {
+        Ok(ForkResult::Child) => {
+            if let Some(group) = group {
+                prepare_child(group.pgid, group.tty);
+            }
+            // traps are not kept in the child, but signals ignored by them
+            signals::reset_child();
+            shell.traps.retain(|_, action| action.is_empty());
+            // jobs inside the child are not under job control
+            shell.tty = None;
+            shell.subshell = true;
+            let status = f(shell);
+            io::stdout().flush().ok();
+            unsafe { libc::_exit(status) }
+        }
+        Ok(ForkResult::Parent { child }) => Started::Child(child),
+        Err(_) => {
+            eprintln!("llysh: cannot fork");
+            Started::Failed(1)
+        }
+    }
+}
+
+/// an error of expanding words leaves a shell running a script, reported here
+/// as the caller would
+fn expansion_error(err: String, shell: &mut Shell) -> String {
+    if !shell.interactive {
+        eprintln!("llysh: {}", err);
+        exit_shell(1, shell);
+    }
+    err
+}
+
+/// apply redirections to the fds of a command from left to right
+fn apply_redirects(
+    redirects: &[Redirect],
+    shell: &mut Shell,
+    fds: &mut FdTable,
+) -> Result<(), String> {
+    for redirect in redirects {
+        let word = match &redirect.target {
+            RedirectTarget::HereDoc(here_doc) => {
+                let body = match here_doc.expand {
+                    true => expand::expand_here_doc(&here_doc.body, shell)
+                        .map_err(|err| expansion_error(err, shell))?,
+                    false => here_doc.body.clone(),
+                };
+                text_file(&body)
+                    .and_then(|file| fds.set(redirect.fd.unwrap_or(0), Some(file)))
+                    .map_err(|err| format!("here-document: {}", strerror(&err)))?;
+                continue;
+            }
+            RedirectTarget::Word(word) => {
+                expand::expand_string(word, shell).map_err(|err| expansion_error(err, shell))?
+            }
+        };
+        let error = |err: io::Error| format!("{}: {}", word, strerror(&err));
+        let open = |read, write, append| open_file(&word, read, write, append).map_err(error);
+        // `set -C` keeps regular files from being overwritten, but by `>|`
+        let clobbers = matches!(redirect.op, Op::Great | Op::AndGreat)
+            || (redirect.op == Op::GreatAnd
+                && redirect.fd.is_none()
+                && word != "-"
+                && word.parse::<i32>().is_err());
+        if clobbers && shell.options.noclobber && Path::new(&word).is_file() {
+            return Err(format!("{}: cannot overwrite existing file", word));
+        }
+        let input = redirect.fd.unwrap_or(0);
+        let output = redirect.fd.unwrap_or(1);
+        match redirect.op {
+            Op::Less => fds.set(input, Some(open(true, false, false)?)),
+            Op::Great | Op::Clobber => fds.set(output, Some(open(false, true, false)?)),
+            Op::DGreat => fds.set(output, Some(open(false, true, true)?)),
+            Op::LessGreat => fds.set(input, Some(open(true, true, false)?)),
+            Op::TLess => {
+                text_file(&(word.clone() + "\n")).and_then(|file| fds.set(input, Some(file)))
+            }
+            Op::LessAnd | Op::GreatAnd => {
+                let fd = if redirect.op == Op::LessAnd {
+                    input
+                } else {
+                    output
+                };
+                if word == "-" {
+                    fds.set(fd, None)
+                } else if let Ok(source) = word.parse() {
+                    fds.duplicate(fd, source)
+                } else if redirect.op == Op::GreatAnd && redirect.fd.is_none() {
+                    // `>& file` is the same as `&> file`
+                    let file = open(false, true, false)?;
+                    fds.set(1, Some(file)).and_then(|_| fds.duplicate(2, 1))
+                } else {
+                    return Err(format!("{}: ambiguous redirect", word));
+                }
+            }
+            Op::AndGreat | Op::AndDGreat => {
+                let file = open(false, true, redirect.op == Op::AndDGreat)?;
+                fds.set(1, Some(file)).and_then(|_| fds.duplicate(2, 1))
+            }
+            _ => unreachable!(),
+        }
+        .map_err(error)?;
+    }
+    Ok(())
+}
+
+/// expand the words of a command into its arguments
+/// braces, then parameters and such, then pathnames
+fn get_tokens(words: &[String], shell: &mut Shell) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    for word in words.iter().flat_map(|word| glob::expand_braces(word)) {
+        for field in expand::expand_word(&word, shell)? {
+            if !field.has_pattern {
+                tokens.push(field.value);
+                continue;
+            }
+            let paths = glob::expand_path(&field.pattern, shell.options.globstar);
+            if !paths.is_empty() {
+                tokens.extend(paths);
+            } else if shell.options.failglob {
+                return Err(format!("no match: {}", field.value));
+            } else if !shell.options.nullglob {
+                tokens.push(field.value);
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// run commands in a child shell, return what they write to stdout
+/// `$?` is set to the status of the child
+pub fn capture_output(source: &str, shell: &mut Shell) -> String {
+    let (mut read, write) = match make_pipe() {
+        Some(pipe) => pipe,
+        None => {
+            eprintln!("llysh: cannot create pipe");
+            return String::new();
+        }
+    };
+    let started = fork_child(shell, None, |shell| {
+        if let Err(err) = redirect::install(&[(1, Some(write.as_raw_fd()))]) {
+            eprintln!("llysh: {}", strerror(&err));
+            return 1;
+        }
+        run_source(source, shell)
+    });
+    // the read end only gets EOF when no copy of the write end is left
+    drop(write);
+    let mut output = Vec::new();
+    read.read_to_end(&mut output).ok();
+    shell.status = match started {
+        Started::Child(pid) => {
+            let mut job = Job::new(None, String::new(), vec![pid]);
+            job.wait();
+            job.statuses().last().copied().unwrap_or(0)
+        }
+        Started::Finished(status) | Started::Failed(status) => status,
+    };
+    String::from_utf8_lossy(&output).into_owned()
+}
+
+/// run commands in a child shell with its stdout, or stdin for `>(...)`, on
+/// a pipe, return the `/dev/fd/N` path of the shell's end of it, like `<(...)`
+/// the end stays open in `shell.substitutions` for the command to use
+pub fn process_substitution(
+    source: &str,
+    input: bool,
+    shell: &mut Shell,
+) -> Result<String, String> {
+    let (read, write) = make_pipe().ok_or("cannot create pipe")?;
+    let (ours, theirs, fd) = match input {
+        true => (write, read, 0),
+        false => (read, write, 1),
+    };
+    let ours_fd = ours.as_raw_fd();
+    let started = fork_child(shell, None, |shell| {
+        // the child does not keep the other end, so it sees EOF or SIGPIPE
+        // when the command is done with it
+        shell.substitutions.clear();
+        unsafe { libc::close(ours_fd) };
+        if let Err(err) = redirect::install(&[(fd, Some(theirs.as_raw_fd()))]) {
+            eprintln!("llysh: {}", strerror(&err));
+            return 1;
+        }
+        run_source(source, shell)
+    });
+    match started {
+        Started::Child(pid) => shell.jobs.add_substitution(pid),
+        Started::Finished(_) | Started::Failed(_) => {
+            return Err("process substitution failed".to_string())
+        }
+    }
+    let path = format!("/dev/fd/{}", ours.as_raw_fd());
+    shell.substitutions.push(ours);
+    Ok(path)
+}
@@ -0,0 +1,565 @@
+use crate::arith;
+use crate::exec::{capture_output, process_substitution};
+use crate::glob;
+use crate::shell::Shell;
+use crate::vars::is_name;
+use nix::unistd::User;
+use std::mem;
+
+/// a field produced by expanding a word
+#[derive(Debug, Default, Clone)]
+pub struct Field {
+    /// the text after quote removal
+    pub value: String,
+    /// the text as a pattern, with pattern characters from quotes escaped
+    pub pattern: String,
+    /// whether an unquoted `*`, `?` or `[` is in it, so it is a pathname pattern
+    pub has_pattern: bool,
+}
+
+/// expand a word into fields: tilde, parameters, command substitutions,
+/// field splitting on `$IFS` and quote removal
+pub fn expand_word(word: &str, shell: &mut Shell) -> Result<Vec<Field>, String> {
+    let mut expander = Expander::new(shell, true);
+    expander.word(&word.chars().collect::<Vec<_>>())?;
+    Ok(expander.finish())
+}
+
+/// expand a word into one string without field splitting, like a redirection target
+pub fn expand_string(word: &str, shell: &mut Shell) -> Result<String, String> {
+    let mut expander = Expander::new(shell, false);
+    expander.word(&word.chars().collect::<Vec<_>>())?;
+    Ok(expander.finish_joined().value)
+}
+
+/// expand a word into a pattern without field splitting, like a pattern of `case`
+/// quoted pattern characters are escaped
+pub fn expand_pattern(word: &str, shell: &mut Shell) -> Result<String, String> {
+    let mut expander = Expander::new(shell, false);
+    expander.word(&word.chars().collect::<Vec<_>>())?;
+    Ok(expander.finish_joined().pattern)
+}
+
+/// expand parameters and command substitutions in the body of a here-document
+/// quotes are kept, backslash only escapes `$`, `` ` ``, `\` and newline
+pub fn expand_here_doc(text: &str, shell: &mut Shell) -> Result<String, String> {
+    let mut expander = Expander::new(shell, false);
+    expander.quoted(&text.chars().collect::<Vec<_>>(), 0, true)?;
+    Ok(expander.finish_joined().value)
+}
+
+/// expand parameters and command substitutions in an arithmetic expression,
+/// then evaluate it, like `$(( expr ))`
+pub fn expand_arith(expr: &str, shell: &mut Shell) -> Result<i64, String> {
+    let expr = expand_string(expr, shell)?;
+    arith::evaluate(&expr, &mut shell.vars)
+}
+
+/// value of a parameter, None if unset
+pub fn lookup_param(name: &str, shell: &Shell) -> Option<String> {
+    match name {
+        "?" => Some(shell.status.to_string()),
+        "-" => Some(shell.options.flags() + if shell.interactive { "i" } else { "" }),
+        "#" => Some((shell.args.len() - 1).to_string()),
+        "@" => Some(shell.args[1..].join(" ")),
+        // joined by the first character of `$IFS`, a space if unset
+        "*" => {
+            let separator = ifs(shell).chars().next().map(String::from);
+            Some(shell.args[1..].join(&separator.unwrap_or_default()))
+        }
+        _ if name.bytes().all(|b| b.is_ascii_digit()) => name
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| shell.args.get(n).cloned()),
+        "PIPESTATUS" => Some(
+            shell
+                .pipestatus
+                .iter()
+                .map(|status| status.to_string())
+                .collect::<Vec<_>>()
+                .join(" "),
+        ),
+        _ => shell.vars.get(name).map(str::to_string),
+    }
+}
+
+/// the characters that split unquoted expansions into fields
+fn ifs(shell: &Shell) -> String {
+    shell.vars.get("IFS").unwrap_or(" \t\n").to_string()
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// a parameter name at the head of chars, the number of chars it takes
+fn param_name_len(chars: &[char]) -> usize {
+    match chars.first() {
+        Some('?' | '#' | '@' | '*' | '-' | '0'..='9') => 1,
+        Some(&c) if is_name_char(c) => chars.iter().take_while(|&&c| is_name_char(c)).count(),
+        _ => 0,
+    }
+}
+
+/// the index of the char closing the one opened at `start`, like `)` for `$(`
+/// quotes and escapes inside are skipped. chars.len() if not closed
+pub fn find_close(chars: &[char], start: usize, open: char, close: char) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '\'' => {
+                i += 1;
+                while i < chars.len() && chars[i] != '\'' {
+                    i += 1;
+                }
+            }
+            '"' => {
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    if chars[i] == '\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+            }
+            c if c == open => depth += 1,
+            c if c == close => {
+                depth -= 1;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+    chars.len()
+}
+
+/// the state of expanding one word
+struct Expander<'a> {
+    shell: &'a mut Shell,
+    /// whether unquoted expansions are split into fields
+    split: bool,
+    fields: Vec<Field>,
+    current: Field,
+    /// whether the current field exists even if empty, like `""`
+    present: bool,
+}
+
+impl<'a> Expander<'a> {
+    fn new(shell: &'a mut Shell, split: bool) -> Expander<'a> {
+        Expander {
+            shell,
+            split,
+            fields: Vec::new(),
+            current: Field::default(),
+            present: false,
+        }
+    }
+
+    fn finish(mut self) -> Vec<Field> {
+        self.end_field(false);
+        self.fields
+    }
+
+    /// all fields as one, separated by spaces
+    fn finish_joined(self) -> Field {
+        let mut joined = Field::default();
+        for (i, field) in self.finish().into_iter().enumerate() {
+            if i > 0 {
+                joined.value.push(' ');
+                joined.pattern.push(' ');
+            }
+            joined.value += &field.value;
+            joined.pattern += &field.pattern;
+            joined.has_pattern |= field.has_pattern;
+        }
+        joined
+    }
+
+    /// start a new field, keep the current one if it is not empty or `force`
+    fn end_field(&mut self, force: bool) {
+        let field = mem::take(&mut self.current);
+        if force || self.present || !field.value.is_empty() {
+            self.fields.push(field);
+        }
+        self.present = false;
+    }
+
+    /// append text that is not split
+    fn push(&mut self, text: &str, quoted: bool) {
+        self.present = true;
+        self.current.value.push_str(text);
+        if quoted {
+            self.current.pattern.push_str(&glob::escape(text));
+        } else {
+            self.current.pattern.push_str(text);
+            self.current.has_pattern |= text.contains(['*', '?', '[']);
+        }
+    }
+
+    /// append the result of an expansion, split into fields if unquoted
+    fn push_expansion(&mut self, text: &str, quoted: bool) {
+        if quoted || !self.split {
+            return self.push(text, quoted);
+        }
+        let ifs = ifs(self.shell);
+        // IFS whitespace ends the field only if something follows
+        let mut pending = false;
+        for c in text.chars() {
+            if !ifs.contains(c) {
+                if pending {
+                    self.end_field(false);
+                    pending = false;
+                }
+                self.push(c.encode_utf8(&mut [0; 4]), false);
+            } else if c.is_whitespace() {
+                pending = true;
+            } else {
+                self.end_field(true);
+                pending = false;
+            }
+        }
+        if pending {
+            self.end_field(false);
+        }
+    }
+
+    /// expand an unquoted word
+    fn word(&mut self, chars: &[char]) -> Result<(), String> {
+        let mut i = 0;
+        if chars.first() == Some(&'~') {
+            i = self.tilde(chars);
+        }
+        while i < chars.len() {
+            match chars[i] {
+                '\\' => {
+                    if let Some(c) = chars.get(i + 1) {
+                        self.push(c.encode_utf8(&mut [0; 4]), true);
+                    }
+                    i += 2;
+                }
+                '\'' => {
+                    let end = chars[i + 1..]
+                        .iter()
+                        .position(|&c| c == '\'')
+                        .map_or(chars.len(), |n| i + 1 + n);
+                    let text: String = chars[i + 1..end].iter().collect();
+                    self.push(&text, true);
+                    i = end + 1;
+                }
+                '"' => {
+                    let before = self.present;
+                    self.present = true;
+                    let (end, only_empty_at) = self.quoted(chars, i + 1, false)?;
+                    // `"$@"` without positional parameters gives no field at all
+                    if only_empty_at {
+                        self.present = before;
+                    }
+                    i = end;
+                }
+                '$' => i = self.dollar(chars, i, false)?.0,
+                '`' => i = self.backquote(chars, i, false)?,
+                // `<(...)` or `>(...)`, a file to read what the commands write
+                // or to write what they read
+                c @ ('<' | '>') if chars.get(i + 1) == Some(&'(') => {
+                    let end = find_close(chars, i + 1, '(', ')');
+                    let source: String = chars[i + 2..end.min(chars.len())].iter().collect();
+                    let path = process_substitution(&source, c == '>', self.shell)?;
+                    self.push(&path, true);
+                    i = end + 1;
+                }
+                c => {
+                    self.push(c.encode_utf8(&mut [0; 4]), false);
+                    i += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// `~` or `~user` at the start of a word, return the index after it
+    fn tilde(&mut self, chars: &[char]) -> usize {
+        let end = chars.iter().position(|&c| c == '/').unwrap_or(chars.len());
+        let user: String = chars[1..end].iter().collect();
+        if !user
+            .chars()
+            .all(|c| is_name_char(c) || c == '-' || c == '.')
+        {
+            return 0;
+        }
+        let home = match user.is_empty() {
+            true => self.shell.vars.get("HOME").map(str::to_string),
+            false => User::from_name(&user)
+                .ok()
+                .flatten()
+                .map(|user| user.dir.to_string_lossy().into_owned()),
+        };
+        match home {
+            Some(home) => {
+                self.push(&home, true);
+                end
+            }
+            None => 0,
+        }
+    }
+
+    /// expand the text after `"` up to the closing one, or all of it in a here-document
+    /// return the index after the quote, and whether there was nothing but an empty `$@`
+    fn quoted(
+        &mut self,
+        chars: &[char],
+        mut i: usize,
+        here_doc: bool,
+    ) -> Result<(usize, bool), String> {
+        let mut other = false;
+        let mut empty_at = false;
+        while i < chars.len() {
+            match chars[i] {
+                '"' if !here_doc => return Ok((i + 1, empty_at && !other)),
+                '\\' => {
+                    match chars.get(i + 1) {
+                        Some('\n') => (),
+                        Some('"') if !here_doc => self.push("\"", true),
+                        Some(&c @ ('$' | '`' | '\\')) => {
+                            self.push(c.encode_utf8(&mut [0; 4]), true)
+                        }
+                        Some(&c) => self.push(&format!("\\{}", c), true),
+                        None => self.push("\\", true),
+                    }
+                    other = true;
+                    i += 2;
+                }
+                '$' => {
+                    let (end, is_empty_at) = self.dollar(chars, i, true)?;
+                    empty_at |= is_empty_at;
+                    other |= !is_empty_at;
+                    i = end;
+                }
+                '`' => {
+                    other = true;
+                    i = self.backquote(chars, i, true)?;
+                }
+                c => {
+                    other = true;
+                    self.push(c.encode_utf8(&mut [0; 4]), true);
+                    i += 1;
+                }
+            }
+        }
+        Ok((i, empty_at && !other))
+    }
+
+    /// expand what starts with `$` at `i`, return the index after it
+    /// and whether it was `"$@"` without positional parameters
+    fn dollar(&mut self, chars: &[char], i: usize, quoted: bool) -> Result<(usize, bool), String> {
+        match chars.get(i + 1) {
+            // `$(( expr ))`, unless the parentheses close like `$( (cmd) )`
+            Some('(')
+                if chars.get(i + 2) == Some(&'(')
+                    && find_close(chars, i + 2, '(', ')') + 1
+                        == find_close(chars, i + 1, '(', ')') =>
+            {
+                let end = find_close(chars, i + 1, '(', ')');
+                let expr: String = chars[i + 3..end - 1].iter().collect();
+                let value = expand_arith(&expr, self.shell)?;
+                self.push_expansion(&value.to_string(), quoted);
+                Ok((end + 1, false))
+            }
+            Some('(') => {
+                let end = find_close(chars, i + 1, '(', ')');
+                let source: String = chars[i + 2..end.min(chars.len())].iter().collect();
+                self.command_substitution(&source, quoted);
+                Ok((end + 1, false))
+            }
+            Some('{') => {
+                let end = find_close(chars, i + 1, '{', '}');
+                if end == chars.len() {
+                    return Err("bad substitution: no closing `}`".to_string());
+                }
+                self.braced(&chars[i + 2..end], quoted)?;
+                Ok((end + 1, false))
+            }
+            Some('@') => Ok((i + 2, self.positional(quoted))),
+            _ => {
+                let len = param_name_len(&chars[i + 1..]);
+                if len == 0 {
+                    self.push("$", quoted);
+                    return Ok((i + 1, false));
+                }
+                let name: String = chars[i + 1..i + 1 + len].iter().collect();
+                let value = lookup_param(&name, self.shell);
+                let value = self.set_value(&name, value)?;
+                self.push_expansion(&value, quoted);
+                Ok((i + 1 + len, false))
+            }
+        }
+    }
+
+    /// the value of a parameter, empty if unset, which is an error for `set -u`
+    fn set_value(&mut self, name: &str, value: Option<String>) -> Result<String, String> {
+        match value {
+            Some(value) => Ok(value),
+            None if self.shell.options.nounset && !matches!(name, "@" | "*") => {
+                Err(format!("{}: unbound variable", name))
+            }
+            None => Ok(String::new()),
+        }
+    }
+
+    /// `$@`, every positional parameter becomes a field
+    /// return whether there are none in quotes
+    fn positional(&mut self, quoted: bool) -> bool {
+        let args = self.shell.args[1..].to_vec();
+        for (n, arg) in args.iter().enumerate() {
+            if n > 0 {
+                self.end_field(true);
+            }
+            self.push_expansion(arg, quoted);
+        }
+        quoted && args.is_empty()
+    }
+
+    /// `${...}` with its contents
+    fn braced(&mut self, chars: &[char], quoted: bool) -> Result<(), String> {
+        let text: String = chars.iter().collect();
+        let bad_substitution = || format!("${{{}}}: bad substitution", text);
+        // `${#name}`, the length of the value
+        if chars.len() > 1 && chars[0] == '#' {
+            let name: String = chars[1..].iter().collect();
+            if param_name_len(&chars[1..]) != chars.len() - 1 {
+                return Err(bad_substitution());
+            }
+            let value = lookup_param(&name, self.shell);
+            let value = self.set_value(&name, value)?;
+            self.push_expansion(&value.chars().count().to_string(), quoted);
+            return Ok(());
+        }
+        let len = param_name_len(chars);
+        if len == 0 {
+            return Err(bad_substitution());
+        }
+        let name: String = chars[..len].iter().collect();
+        let mut rest = &chars[len..];
+        let value = if rest.first() == Some(&'[') {
+            // `${PIPESTATUS[n]}`
+            let end = rest
+                .iter()
+                .position(|&c| c == ']')
+                .ok_or_else(bad_substitution)?;
+            let index: String = rest[1..end].iter().collect();
+            rest = &rest[end + 1..];
+            match (name.as_str(), index.as_str()) {
+                ("PIPESTATUS", "@" | "*") => lookup_param(&name, self.shell),
+                ("PIPESTATUS", _) => {
+                    let n: usize = index.parse().map_err(|_| bad_substitution())?;
+                    self.shell
+                        .pipestatus
+                        .get(n)
+                        .map(|status| status.to_string())
+                }
+                (_, "0") => lookup_param(&name, self.shell),
+                _ => None,
+            }
+        } else {
+            lookup_param(&name, self.shell)
+        };
+        if rest.is_empty() {
+            let value = self.set_value(&name, value)?;
+            self.push_expansion(&value, quoted);
+            return Ok(());
+        }
+
+        // with `:` an empty value counts as unset
+        let colon = rest[0] == ':';
+        if colon {
+            rest = &rest[1..];
+        }
+        let op: String = match rest {
+            ['#', '#', ..] | ['%', '%', ..] => rest[..2].iter().collect(),
+            ['-' | '=' | '?' | '+', ..] => rest[..1].iter().collect(),
+            ['#' | '%', ..] if !colon => rest[..1].iter().collect(),
+            _ => return Err(bad_substitution()),
+        };
+        let word = &rest[op.len()..];
+        let unset = match &value {
+            Some(value) => colon && value.is_empty(),
+            None => true,
+        };
+        match op.as_str() {
+            "-" | "=" | "?" if !unset => self.push_expansion(&value.unwrap_or_default(), quoted),
+            "-" => {
+                let word = self.sub_expansion(word, quoted)?;
+                self.push_expansion(&word.value, quoted);
+            }
+            "=" => {
+                if !is_name(&name) {
+                    return Err(format!("${}: cannot assign in this way", name));
+                }
+                let word = self.sub_expansion(word, quoted)?;
+                self.shell.vars.set(&name, &word.value)?;
+                self.push_expansion(&word.value, quoted);
+            }
+            "?" => {
+                let word = self.sub_expansion(word, quoted)?;
+                return Err(match word.value.is_empty() {
+                    true => format!("{}: parameter null or not set", name),
+                    false => format!("{}: {}", name, word.value),
+                });
+            }
+            "+" => {
+                if !unset {
+                    let word = self.sub_expansion(word, quoted)?;
+                    self.push_expansion(&word.value, quoted);
+                }
+            }
+            _ => {
+                let value = self.set_value(&name, value)?;
+                let pattern = self.sub_expansion(word, quoted)?.pattern;
+                let value = match op.as_str() {
+                    "#" => glob::trim_prefix(&value, &pattern, false),
+                    "##" => glob::trim_prefix(&value, &pattern, true),
+                    "%" => glob::trim_suffix(&value, &pattern, false),
+                    _ => glob::trim_suffix(&value, &pattern, true),
+                };
+                self.push_expansion(&value, quoted);
+            }
+        }
+        Ok(())
+    }
+
+    /// expand the word of `${name:-word}` and such, not split
+    fn sub_expansion(&mut self, chars: &[char], quoted: bool) -> Result<Field, String> {
+        let mut expander = Expander::new(self.shell, false);
+        match quoted {
+            true => expander.quoted(chars, 0, true).map(|_| ())?,
+            false => expander.word(chars)?,
+        }
+        Ok(expander.finish_joined())
+    }
+
+    /// `` `command` `` at `i`, return the index after it
+    fn backquote(&mut self, chars: &[char], i: usize, quoted: bool) -> Result<usize, String> {
+        let mut source = String::new();
+        let mut j = i + 1;
+        while j < chars.len() && chars[j] != '`' {
+            // backslash only escapes these inside backquotes
+            if chars[j] == '\\' && matches!(chars.get(j + 1), Some('$' | '`' | '\\')) {
+                j += 1;
+            }
+            source.push(chars[j]);
+            j += 1;
+        }
+        self.command_substitution(&source, quoted);
+        Ok(j + 1)
+    }
+
+    /// run commands and expand to what they print, without trailing newlines
+    fn command_substitution(&mut self, source: &str, quoted: bool) {
+        let output = capture_output(source, self.shell);
+        self.push_expansion(output.trim_end_matches('\n'), quoted);
+    }
+}
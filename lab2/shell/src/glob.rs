@@ -0,0 +1,391 @@
+use crate::expand::find_close;
+use std::fs;
+use std::path::Path;
+
+/// whether a character has a special meaning in patterns
+pub fn is_pattern_char(c: char) -> bool {
+    matches!(c, '*' | '?' | '[' | ']' | '\\')
+}
+
+/// escape the pattern characters of text, so that it only matches itself
+pub fn escape(text: &str) -> String {
+    let mut pattern = String::new();
+    for c in text.chars() {
+        if is_pattern_char(c) {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern
+}
+
+/// whether the whole text matches a shell pattern with `*`, `?`, `[...]`
+/// and backslash escapes
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    match_from(&pattern, &text)
+}
+
+fn match_from(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // where to resume after the last `*`, (pattern index, text index)
+    let mut star = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, t));
+                p += 1;
+                continue;
+            }
+            Some('?') => {
+                p += 1;
+                t += 1;
+                continue;
+            }
+            Some('[') => {
+                if let Some((matched, end)) = match_bracket(pattern, p, text[t]) {
+                    if matched {
+                        p = end;
+                        t += 1;
+                        continue;
+                    }
+                } else if text[t] == '[' {
+                    // an unclosed `[` matches itself
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+            }
+            Some('\\') if pattern.get(p + 1) == Some(&text[t]) => {
+                p += 2;
+                t += 1;
+                continue;
+            }
+            Some('\\') => (),
+            Some(&c) if c == text[t] => {
+                p += 1;
+                t += 1;
+                continue;
+            }
+            _ => (),
+        }
+        // mismatch, let the last `*` take one more character
+        match star {
+            Some((star_p, star_t)) => {
+                p = star_p;
+                t = star_t + 1;
+                star = Some((star_p, star_t + 1));
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// match a character against the bracket expression starting at `start`
+/// return whether it matches and the index after `]`, or None if not closed
+fn match_bracket(pattern: &[char], start: usize, c: char) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+    let negate = matches!(pattern.get(i), Some('!' | '^'));
+    if negate {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let mut low = *pattern.get(i)?;
+        if low == ']' && !first {
+            return Some((matched != negate, i + 1));
+        }
+        first = false;
+        if low == '\\' {
+            i += 1;
+            low = *pattern.get(i)?;
+        }
+        i += 1;
+        // a range like `a-z`, unless `-` is the last one
+        if pattern.get(i) == Some(&'-') && !matches!(pattern.get(i + 1), Some(']') | None) {
+            let mut high = pattern[i + 1];
+            i += 2;
+            if high == '\\' {
+                high = *pattern.get(i)?;
+                i += 1;
+            }
+            matched |= low <= c && c <= high;
+        } else {
+            matched |= low == c;
+        }
+    }
+}
+
+/// remove the shortest or longest prefix matching the pattern, like `${VAR#pattern}`
+pub fn trim_prefix(text: &str, pattern: &str, longest: bool) -> String {
+    let mut ends: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
+    ends.push(text.len());
+    if longest {
+        ends.reverse();
+    }
+    match ends.into_iter().find(|&end| matches(pattern, &text[..end])) {
+        Some(end) => text[end..].to_string(),
+        None => text.to_string(),
+    }
+}
+
+/// remove the shortest or longest suffix matching the pattern, like `${VAR%pattern}`
+pub fn trim_suffix(text: &str, pattern: &str, longest: bool) -> String {
+    let mut starts: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
+    starts.push(text.len());
+    if !longest {
+        starts.reverse();
+    }
+    match starts
+        .into_iter()
+        .find(|&start| matches(pattern, &text[start..]))
+    {
+        Some(start) => text[..start].to_string(),
+        None => text.to_string(),
+    }
+}
+
+/// brace expansion of a word as written, like `a{b,c}d` to `abd` and `acd`
+/// or `{1..3}` to `1`, `2` and `3`. quoted braces and those of `${...}` are kept
+pub fn expand_braces(word: &str) -> Vec<String> {
+    let chars: Vec<char> = word.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '\'' | '"' | '`' => {
+                let quote = chars[i];
+                i += 1;
+                while i < chars.len() && chars[i] != quote {
+                    if chars[i] == '\\' && quote != '\'' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+            }
+            '$' if matches!(chars.get(i + 1), Some('{')) => {
+                i = find_close(&chars, i + 1, '{', '}');
+            }
+            '$' if matches!(chars.get(i + 1), Some('(')) => {
+                i = find_close(&chars, i + 1, '(', ')');
+            }
+            '{' => {
+                if let Some((alternatives, end)) = brace_alternatives(&chars, i) {
+                    let prefix: String = chars[..i].iter().collect();
+                    let suffix: String = chars[end + 1..].iter().collect();
+                    return alternatives
+                        .iter()
+                        .flat_map(|alternative| {
+                            expand_braces(&format!("{}{}{}", prefix, alternative, suffix))
+                        })
+                        .collect();
+                }
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+    vec![word.to_string()]
+}
+
+/// the alternatives of the braces opened at `start`, and the index of `}`
+/// None if they are not closed or have neither a comma nor a range
+fn brace_alternatives(chars: &[char], start: usize) -> Option<(Vec<String>, usize)> {
+    let mut depth = 0;
+    let mut alternatives = Vec::new();
+    let mut alternative = String::new();
+    let mut i = start + 1;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\\' | '\'' | '"' | '`' | '$' => {
+                // copied as they are, quoted parts stay quoted
+                let end = match c {
+                    '\\' => i + 1,
+                    '$' => match chars.get(i + 1) {
+                        Some('{') => find_close(chars, i + 1, '{', '}'),
+                        Some('(') => find_close(chars, i + 1, '(', ')'),
+                        _ => i,
+                    },
+                    _ => (i + 1..chars.len())
+                        .find(|&j| chars[j] == c && (c == '\'' || chars[j - 1] != '\\'))
+                        .unwrap_or(chars.len()),
+                };
+                let end = end.min(chars.len() - 1);
+                alternative.extend(&chars[i..=end]);
+                i = end;
+            }
+            '{' => {
+                depth += 1;
+                alternative.push(c);
+            }
+            '}' if depth > 0 => {
+                depth -= 1;
+                alternative.push(c);
+            }
+            '}' => {
+                if alternatives.is_empty() {
+                    return brace_range(&alternative).map(|range| (range, i));
+                }
+                alternatives.push(alternative);
+                return Some((alternatives, i));
+            }
+            ',' if depth == 0 => alternatives.push(std::mem::take(&mut alternative)),
+            _ => alternative.push(c),
+        }
+        i += 1;
+    }
+    None
+}
+
+/// `1..5`, `a..e` or `1..10..2` in braces
+fn brace_range(text: &str) -> Option<Vec<String>> {
+    let parts: Vec<&str> = text.split("..").collect();
+    let step = match parts.len() {
+        2 => 1,
+        3 => parts[2].parse::<i64>().ok()?.checked_abs()?.max(1),
+        _ => return None,
+    };
+    let (start, end) = (parts[0], parts[1]);
+    if let (Ok(start), Ok(end)) = (start.parse::<i64>(), end.parse::<i64>()) {
+        let range: Vec<i64> = match start <= end {
+            true => (start..=end).step_by(step as usize).collect(),
+            false => (end..=start).rev().step_by(step as usize).collect(),
+        };
+        return Some(range.iter().map(i64::to_string).collect());
+    }
+    let (mut start_chars, mut end_chars) = (start.chars(), end.chars());
+    match (
+        start_chars.next(),
+        start_chars.next(),
+        end_chars.next(),
+        end_chars.next(),
+    ) {
+        (Some(start), None, Some(end), None)
+            if start.is_ascii_alphabetic() && end.is_ascii_alphabetic() =>
+        {
+            let (start, end) = (start as u8, end as u8);
+            let range: Vec<u8> = match start <= end {
+                true => (start..=end).step_by(step as usize).collect(),
+                false => (end..=start).rev().step_by(step as usize).collect(),
+            };
+            Some(range.iter().map(|&c| (c as char).to_string()).collect())
+        }
+        _ => None,
+    }
+}
+
+/// remove the backslashes escaping pattern characters
+fn unescape(pattern: &str) -> String {
+    let mut text = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.extend(chars.next()),
+            _ => text.push(c),
+        }
+    }
+    text
+}
+
+/// whether an unescaped `*`, `?` or `[` is in a pattern
+fn has_pattern(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            _ => (),
+        }
+    }
+    false
+}
+
+fn join_path(base: &str, name: &str) -> String {
+    match base {
+        "" => name.to_string(),
+        _ if base.ends_with('/') => format!("{}{}", base, name),
+        _ => format!("{}/{}", base, name),
+    }
+}
+
+/// entries of a directory, `""` for the current one, hidden ones only if `hidden`
+/// (name, whether it is a directory not through a symlink)
+fn read_dir(dir: &str, hidden: bool) -> Vec<(String, bool)> {
+    let entries = match fs::read_dir(if dir.is_empty() { "." } else { dir }) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+            (hidden || !name.starts_with('.')).then_some((name, is_dir))
+        })
+        .collect()
+}
+
+/// everything under a directory, recursively, hidden ones excluded
+/// with `dirs_only`, only the directory itself and the directories under it
+fn walk(dir: &str, dirs_only: bool, paths: &mut Vec<String>) {
+    if dirs_only {
+        paths.push(dir.to_string());
+    }
+    for (name, is_dir) in read_dir(dir, false) {
+        let path = join_path(dir, &name);
+        if is_dir {
+            walk(&path, dirs_only, paths);
+            if !dirs_only {
+                paths.push(path);
+            }
+        } else if !dirs_only {
+            paths.push(path);
+        }
+    }
+}
+
+/// pathnames matching a pattern, sorted. hidden files only match a pattern
+/// starting with `.`, and `**` matches any levels of directories with `globstar`
+pub fn expand_path(pattern: &str, globstar: bool) -> Vec<String> {
+    let components: Vec<&str> = pattern.split('/').filter(|c| !c.is_empty()).collect();
+    let mut paths = vec![match pattern.starts_with('/') {
+        true => "/".to_string(),
+        false => String::new(),
+    }];
+    for (i, component) in components.iter().enumerate() {
+        let last = i == components.len() - 1;
+        let mut next = Vec::new();
+        for base in &paths {
+            if globstar && *component == "**" {
+                walk(base, !last, &mut next);
+            } else if !has_pattern(component) {
+                let path = join_path(base, &unescape(component));
+                if fs::symlink_metadata(&path).is_ok() {
+                    next.push(path);
+                }
+            } else {
+                for (name, _) in read_dir(base, component.starts_with('.')) {
+                    if matches(component, &name) {
+                        next.push(join_path(base, &name));
+                    }
+                }
+            }
+        }
+        paths = next;
+    }
+    if pattern.ends_with('/') {
+        paths.retain(|path| Path::new(path).is_dir());
+        paths.iter_mut().for_each(|path| path.push('/'));
+    }
+    // `**` under the current directory starts with an empty path
+    paths.retain(|path| !path.is_empty());
+    paths.sort();
+    paths.dedup();
+    paths
+}
@@ -1,41 +1,310 @@
+use crate::parser;
+use nix::fcntl::{flock, FlockArg};
 use std::{
+    collections::BTreeMap,
     fs::{File, OpenOptions},
-    io::{self, BufRead, Write},
+    io::{self, BufRead, Seek, SeekFrom, Write},
+    os::unix::io::AsRawFd,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
+/// `HISTSIZE` when not set, the number of commands kept in memory
+pub const DEFAULT_SIZE: &str = "1000";
+/// `HISTFILESIZE` when not set, the number of commands kept in the file
+pub const DEFAULT_FILE_SIZE: &str = "2000";
+/// `HISTCONTROL` when not set
+pub const DEFAULT_CONTROL: &str = "ignoredups";
+
+/// a command line in the history, with where, when and how it ran if known
+#[derive(Debug, Clone, Default)]
+pub struct Entry {
+    pub command: String,
+    /// seconds since the epoch when it started
+    pub time: Option<i64>,
+    /// the directory it ran in
+    pub cwd: Option<String>,
+    pub status: Option<i32>,
+}
+
+#[derive(Default)]
 pub struct History {
-    file_name: String,
-    lines: Vec<String>,
+    /// None for a history kept in memory only
+    file_name: Option<String>,
+    entries: Vec<Entry>,
+    /// the last entry is running, and saved to the file once it finishes
+    pending: bool,
+    /// the most entries kept in memory and in the file, no limit for None
+    size: Option<usize>,
+    file_size: Option<usize>,
+    /// skip commands starting with a space
+    ignore_space: bool,
+    /// skip a command the same as the last one
+    ignore_dups: bool,
+    /// remove the earlier entries of a command when adding it
+    erase_dups: bool,
 }
 
-impl History {
-    pub fn new(file_name: String) -> Result<History, io::Error> {
-        let file = match File::open(&file_name) {
-            Ok(file) => file,
-            Err(_) => {
-                File::create(&file_name).unwrap();
-                File::open(&file_name)?
+/// parse the history file format: a command per line as in old files, or
+/// commands after a `#TIME STATUS CWD` line, which may take several lines
+/// lines which are not valid UTF-8 are read with replacement characters
+fn parse_entries(reader: impl BufRead) -> io::Result<Vec<Entry>> {
+    let mut entries: Vec<Entry> = Vec::new();
+    // whether the last entry has a metadata line, and so may take more lines
+    let mut extended = false;
+    for line in reader.split(b'\n') {
+        let line = String::from_utf8_lossy(&line?).into_owned();
+        if let Some(entry) = parse_metadata(&line) {
+            entries.push(entry);
+            extended = true;
+        } else if let Some(entry) = entries
+            .last_mut()
+            .filter(|entry| extended && is_open(entry))
+        {
+            if !entry.command.is_empty() {
+                entry.command.push('\n');
             }
+            entry.command += &line;
+        } else if !line.is_empty() {
+            entries.push(Entry {
+                command: line,
+                ..Entry::default()
+            });
+            extended = false;
+        }
+    }
+    entries.retain(|entry| !entry.command.is_empty());
+    Ok(entries)
+}
+
+/// whether an entry goes on with the next line, as it has no command yet or
+/// its command is not complete, like `if` without `fi`
+fn is_open(entry: &Entry) -> bool {
+    entry.command.is_empty() || parser::is_incomplete(&entry.command, &BTreeMap::new())
+}
+
+/// `#TIME STATUS CWD`, where the status may be `-` and the rest missing. the
+/// time is 0 and the directory empty for an entry without them
+fn parse_metadata(line: &str) -> Option<Entry> {
+    let rest = line.strip_prefix('#')?;
+    let mut fields = rest.splitn(3, ' ');
+    let time = fields.next()?.parse().ok()?;
+    let status = fields.next().and_then(|status| status.parse().ok());
+    let cwd = fields
+        .next()
+        .filter(|cwd| !cwd.is_empty())
+        .map(str::to_string);
+    Some(Entry {
+        command: String::new(),
+        time: Some(time).filter(|&time| time != 0),
+        cwd,
+        status,
+    })
+}
+
+/// an entry as written to the history file, always after a metadata line so
+/// that it is not taken as more lines of the entry before it
+fn format_entry(entry: &Entry) -> String {
+    let time = entry.time.unwrap_or(0);
+    let status = match entry.status {
+        Some(status) => status.to_string(),
+        None => "-".to_string(),
+    };
+    let cwd = entry.cwd.as_deref().unwrap_or_default();
+    format!("#{} {} {}\n{}\n", time, status, cwd, entry.command)
+}
+
+/// open a file and lock it, shared to read and exclusive to write, so that
+/// shells using the same file do not mix their writes. closing it unlocks
+fn open_locked(file_name: &str, options: &OpenOptions, lock: FlockArg) -> io::Result<File> {
+    let file = options.open(file_name)?;
+    flock(file.as_raw_fd(), lock)?;
+    Ok(file)
+}
+
+/// replace the contents of a locked file with the entries, at one write
+fn write_entries(mut file: &File, entries: &[Entry]) -> io::Result<()> {
+    let text: String = entries.iter().map(format_entry).collect();
+    file.set_len(0)?;
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(text.as_bytes())
+}
+
+/// a limit from `HISTSIZE` or `HISTFILESIZE`, none if negative or not a number
+fn parse_limit(value: Option<&str>) -> Option<usize> {
+    value?.trim().parse::<i64>().ok()?.try_into().ok()
+}
+
+impl History {
+    /// load the history file, which may not exist yet
+    pub fn new(file_name: String) -> io::Result<History> {
+        let entries = match open_locked(
+            &file_name,
+            OpenOptions::new().read(true),
+            FlockArg::LockShared,
+        ) {
+            Ok(file) => parse_entries(io::BufReader::new(file))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
         };
-        let lines = io::BufReader::new(file)
-            .lines()
-            .map(|line| line.unwrap())
-            .filter(|line| !line.is_empty())
-            .collect::<Vec<String>>();
-        Ok(History { file_name, lines })
+        Ok(History {
+            file_name: Some(file_name),
+            entries,
+            ..History::default()
+        })
     }
 
-    pub fn push(&mut self, command: &String) {
-        self.lines.push(command.trim().to_string());
-        let mut file = OpenOptions::new()
-            .append(true)
-            .open(&self.file_name)
-            .unwrap();
-        writeln!(file, "{}", command).expect("save history file error");
+    /// take the limits and policies from the values of `HISTSIZE`,
+    /// `HISTFILESIZE` and `HISTCONTROL`, which lists `ignorespace`,
+    /// `ignoredups`, `ignoreboth` and `erasedups` separated by `:`
+    pub fn configure(
+        &mut self,
+        size: Option<&str>,
+        file_size: Option<&str>,
+        control: Option<&str>,
+    ) {
+        self.size = parse_limit(size);
+        self.file_size = parse_limit(file_size);
+        let control: Vec<&str> = control.unwrap_or_default().split(':').collect();
+        let has = |name: &str| control.contains(&name) || control.contains(&"ignoreboth");
+        self.ignore_space = has("ignorespace");
+        self.ignore_dups = has("ignoredups");
+        self.erase_dups = control.contains(&"erasedups");
+        self.truncate();
+    }
+
+    /// add a command about to run in the directory, saved by `finish`
+    /// return false if it is left out by `HISTCONTROL` or `HISTSIZE`
+    pub fn push(&mut self, command: &str, cwd: Option<String>) -> bool {
+        let trimmed = command.trim();
+        if trimmed.is_empty()
+            || (self.ignore_space && command.starts_with(char::is_whitespace))
+            || (self.ignore_dups && self.last().is_some_and(|last| last == trimmed))
+            || self.size == Some(0)
+        {
+            return false;
+        }
+        if self.erase_dups {
+            self.entries.retain(|entry| entry.command != trimmed);
+        }
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs() as i64);
+        self.entries.push(Entry {
+            command: trimmed.to_string(),
+            time: Some(time),
+            cwd,
+            status: None,
+        });
+        self.pending = true;
+        self.truncate();
+        true
+    }
+
+    /// record the status of the command pushed last, and append it to the file
+    /// the file is cut to `HISTFILESIZE` with the entries other shells appended
+    pub fn finish(&mut self, status: i32) -> io::Result<()> {
+        if !std::mem::take(&mut self.pending) {
+            return Ok(());
+        }
+        let entry = match self.entries.last_mut() {
+            Some(entry) => entry,
+            None => return Ok(()),
+        };
+        entry.status = Some(status);
+        let file_name = match &self.file_name {
+            Some(file_name) => file_name,
+            None => return Ok(()),
+        };
+        let mut file = open_locked(
+            file_name,
+            OpenOptions::new().read(true).append(true).create(true),
+            FlockArg::LockExclusive,
+        )?;
+        file.write_all(format_entry(entry).as_bytes())?;
+        if self.file_size.is_none() && !self.erase_dups {
+            return Ok(());
+        }
+        file.seek(SeekFrom::Start(0))?;
+        let mut entries = parse_entries(io::BufReader::new(&file))?;
+        let count = entries.len();
+        if self.erase_dups {
+            // the entry just appended is the last one
+            let last = entries.len().saturating_sub(1);
+            let mut index = 0;
+            entries.retain(|other| {
+                index += 1;
+                index > last || other.command != entry.command
+            });
+        }
+        if let Some(file_size) = self.file_size {
+            entries.drain(..entries.len().saturating_sub(file_size));
+        }
+        if entries.len() != count {
+            write_entries(&file, &entries)?;
+        }
+        Ok(())
+    }
+
+    /// drop the oldest entries beyond `HISTSIZE`
+    fn truncate(&mut self) {
+        if let Some(size) = self.size {
+            self.entries
+                .drain(..self.entries.len().saturating_sub(size));
+        }
+    }
+
+    /// forget all entries, the file is kept
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.pending = false;
+    }
+
+    /// remove the entry numbered `num`, from 1
+    pub fn remove(&mut self, num: usize) -> Option<Entry> {
+        if num == 0 || num > self.size() {
+            return None;
+        }
+        if num == self.size() {
+            self.pending = false;
+        }
+        Some(self.entries.remove(num - 1))
+    }
+
+    /// write all entries to a file, the history file by default
+    /// the last `HISTFILESIZE` entries are written
+    pub fn write(&self, file_name: Option<&str>) -> io::Result<()> {
+        let file_name = file_name.or(self.file_name.as_deref()).unwrap_or_default();
+        let file = open_locked(
+            file_name,
+            OpenOptions::new().write(true).create(true),
+            FlockArg::LockExclusive,
+        )?;
+        let skip = match self.file_size {
+            Some(file_size) => self.entries.len().saturating_sub(file_size),
+            None => 0,
+        };
+        write_entries(&file, &self.entries[skip..])
+    }
+
+    /// append the entries of a file, the history file by default
+    pub fn read(&mut self, file_name: Option<&str>) -> io::Result<()> {
+        let file_name = file_name.or(self.file_name.as_deref()).unwrap_or_default();
+        let file = open_locked(
+            file_name,
+            OpenOptions::new().read(true),
+            FlockArg::LockShared,
+        )?;
+        let entries = parse_entries(io::BufReader::new(file))?;
+        // the entry running goes on being the last one
+        let running = self.pending.then(|| self.entries.pop()).flatten();
+        self.entries.extend(entries);
+        self.entries.extend(running);
+        self.truncate();
+        Ok(())
     }
 
     pub fn size(&self) -> usize {
-        self.lines.len()
+        self.entries.len()
     }
 
     pub fn last(&self) -> Option<&String> {
@@ -43,18 +312,19 @@ impl History {
     }
 
     pub fn get(&self, num: usize) -> Option<&String> {
-        if num > 0 && num - 1 < self.size() {
-            Some(&self.lines[num - 1])
-        } else {
-            None
-        }
+        self.entry(num).map(|entry| &entry.command)
     }
 
     pub fn rget(&self, num: usize) -> Option<&String> {
-        if self.size() > num && self.size() - 1 - num < self.size() {
-            Some(&self.lines[self.size() - 1 - num])
+        if self.size() > num {
+            Some(&self.entries[self.size() - 1 - num].command)
         } else {
             None
         }
     }
+
+    /// the entry numbered `num`, from 1
+    pub fn entry(&self, num: usize) -> Option<&Entry> {
+        num.checked_sub(1).and_then(|index| self.entries.get(index))
+    }
 }
@@ -0,0 +1,295 @@
+use nix::errno::Errno;
+use nix::sys::signal::{kill, killpg, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{getpgrp, setpgid, tcsetpgrp, Pid};
+use std::os::unix::io::RawFd;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    Running,
+    Stopped,
+    Done,
+}
+
+/// a child process of a job
+pub struct Process {
+    pub pid: Pid,
+    /// exit status, once it has finished
+    pub status: Option<i32>,
+    /// the signal that terminated it
+    pub signal: Option<Signal>,
+    /// it was terminated with a core dump
+    pub core_dumped: bool,
+    pub stopped: bool,
+}
+
+impl Process {
+    /// how it was terminated by a signal, like
+    /// `terminated by signal 11 (SIGSEGV) (core dumped)`
+    pub fn termination(&self) -> Option<String> {
+        let signal = self.signal?;
+        let core = if self.core_dumped {
+            " (core dumped)"
+        } else {
+            ""
+        };
+        Some(format!(
+            "terminated by signal {} ({}){}",
+            signal as i32,
+            signal.as_str(),
+            core
+        ))
+    }
+}
+
+/// a pipeline started by the shell, in the foreground or background
+pub struct Job {
+    /// job number shown by `jobs` and used by `%n`, 0 before added to the table
+    pub id: usize,
+    /// process group of the job, None without job control
+    pub pgid: Option<Pid>,
+    /// the command line shown by `jobs`
+    pub command: String,
+    pub processes: Vec<Process>,
+    /// the state last reported to the user
+    reported: JobState,
+}
+
+impl Job {
+    pub fn new(pgid: Option<Pid>, command: String, pids: Vec<Pid>) -> Job {
+        let processes = pids
+            .into_iter()
+            .map(|pid| Process {
+                pid,
+                status: None,
+                signal: None,
+                core_dumped: false,
+                stopped: false,
+            })
+            .collect();
+        Job {
+            id: 0,
+            pgid,
+            command,
+            processes,
+            reported: JobState::Running,
+        }
+    }
+
+    pub fn state(&self) -> JobState {
+        let mut unfinished = self.processes.iter().filter(|p| p.status.is_none());
+        match unfinished.next() {
+            None => JobState::Done,
+            Some(p) if p.stopped || unfinished.any(|p| p.stopped) => JobState::Stopped,
+            _ => JobState::Running,
+        }
+    }
+
+    /// exit statuses of all processes, 0 for those not finished
+    pub fn statuses(&self) -> Vec<i32> {
+        self.processes
+            .iter()
+            .map(|p| p.status.unwrap_or(0))
+            .collect()
+    }
+
+    /// record a status change reported by `waitpid`, return whether it belongs to the job
+    pub fn update(&mut self, wait_status: WaitStatus) -> bool {
+        let process = match self
+            .processes
+            .iter_mut()
+            .find(|p| Some(p.pid) == wait_status.pid())
+        {
+            Some(process) => process,
+            None => return false,
+        };
+        match wait_status {
+            WaitStatus::Exited(_, code) => process.status = Some(code),
+            WaitStatus::Signaled(_, signal, core_dumped) => {
+                process.status = Some(128 + signal as i32);
+                process.signal = Some(signal);
+                process.core_dumped = core_dumped;
+            }
+            WaitStatus::Stopped(..) => process.stopped = true,
+            WaitStatus::Continued(_) => process.stopped = false,
+            _ => (),
+        }
+        true
+    }
+
+    /// block until every process has finished, or the job is stopped
+    pub fn wait(&mut self) {
+        while self.state() == JobState::Running {
+            let process = self
+                .processes
+                .iter_mut()
+                .find(|p| p.status.is_none() && !p.stopped)
+                .unwrap();
+            match waitpid(process.pid, Some(WaitPidFlag::WUNTRACED)) {
+                Ok(wait_status) => {
+                    self.update(wait_status);
+                }
+                Err(Errno::EINTR) => (),
+                // already reaped somewhere else, nothing more to know
+                Err(_) => process.status = Some(process.status.unwrap_or(0)),
+            }
+        }
+        self.reported = self.state();
+    }
+
+    /// send a signal to every process of the job
+    pub fn signal(&self, signal: Signal) -> nix::Result<()> {
+        match self.pgid {
+            Some(pgid) => killpg(pgid, signal),
+            None => self
+                .processes
+                .iter()
+                .filter(|p| p.status.is_none())
+                .try_for_each(|p| kill(p.pid, signal)),
+        }
+    }
+
+    /// let a stopped job run again
+    pub fn resume(&mut self) -> nix::Result<()> {
+        self.signal(Signal::SIGCONT)?;
+        self.processes.iter_mut().for_each(|p| p.stopped = false);
+        self.reported = JobState::Running;
+        Ok(())
+    }
+
+    /// one line of `jobs` output, `current` marks the `%+` and `%-` jobs
+    pub fn describe(&self, current: char) -> String {
+        let state = match self.state() {
+            JobState::Running => "Running".to_string(),
+            JobState::Stopped => "Stopped".to_string(),
+            JobState::Done => match self.processes.last() {
+                Some(process) if process.signal.is_some() => process.termination().unwrap(),
+                Some(Process {
+                    status: Some(status @ 1..),
+                    ..
+                }) => format!("Exit {}", status),
+                _ => "Done".to_string(),
+            },
+        };
+        format!("[{}]{}  {:<24}{}", self.id, current, state, self.command)
+    }
+}
+
+/// the job table
+#[derive(Default)]
+pub struct Jobs {
+    /// ordered so that the last one is the current job `%+`
+    jobs: Vec<Job>,
+    /// processes of process substitutions still running, reaped with the
+    /// jobs but never reported
+    substitutions: Vec<Pid>,
+}
+
+impl Jobs {
+    /// add a job to the table, give it a number if it has none yet
+    pub fn add(&mut self, mut job: Job) -> usize {
+        if job.id == 0 {
+            job.id = self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        }
+        job.reported = job.state();
+        let id = job.id;
+        self.jobs.push(job);
+        id
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<Job> {
+        let index = self.jobs.iter().position(|job| job.id == id)?;
+        Some(self.jobs.remove(index))
+    }
+
+    /// find a job by a job spec: `%n`, `%+`, `%%`, `%-`, `%prefix`, or a bare number
+    pub fn find(&self, spec: Option<&str>) -> Result<usize, String> {
+        let spec = spec.unwrap_or("%+");
+        let body = spec.strip_prefix('%').unwrap_or(spec);
+        let job = match body {
+            "" | "+" | "%" => self.jobs.last(),
+            "-" => self.jobs.iter().rev().nth(1),
+            _ => match body.parse::<usize>() {
+                Ok(id) => self.jobs.iter().find(|job| job.id == id),
+                Err(_) if spec.starts_with('%') => self
+                    .jobs
+                    .iter()
+                    .rev()
+                    .find(|job| job.command.starts_with(body)),
+                Err(_) => None,
+            },
+        };
+        job.map(|job| job.id)
+            .ok_or_else(|| format!("{}: no such job", spec))
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|job| job.id == id)
+    }
+
+    /// lines of `jobs` output
+    pub fn describe(&self) -> Vec<String> {
+        let count = self.jobs.len();
+        self.jobs
+            .iter()
+            .enumerate()
+            .map(|(i, job)| job.describe(current_mark(i, count)))
+            .collect()
+    }
+
+    /// keep track of the process of a process substitution
+    pub fn add_substitution(&mut self, pid: Pid) {
+        self.substitutions.push(pid);
+    }
+
+    /// reap children without blocking, report jobs that changed state
+    /// and forget those done
+    pub fn reap(&mut self) {
+        let flags = WaitPidFlag::WNOHANG | WaitPidFlag::WUNTRACED | WaitPidFlag::WCONTINUED;
+        while let Ok(wait_status) = waitpid(Pid::from_raw(-1), Some(flags)) {
+            if wait_status == WaitStatus::StillAlive {
+                break;
+            }
+            let ended = matches!(
+                wait_status,
+                WaitStatus::Exited(..) | WaitStatus::Signaled(..)
+            );
+            if !self.jobs.iter_mut().any(|job| job.update(wait_status)) && ended {
+                self.substitutions
+                    .retain(|&pid| Some(pid) != wait_status.pid());
+            }
+        }
+        let count = self.jobs.len();
+        for (i, job) in self.jobs.iter_mut().enumerate() {
+            if job.state() != job.reported {
+                job.reported = job.state();
+                println!("{}", job.describe(current_mark(i, count)));
+            }
+        }
+        self.jobs.retain(|job| job.state() != JobState::Done);
+    }
+}
+
+/// `+` for the current job, `-` for the previous one
+fn current_mark(index: usize, count: usize) -> char {
+    match count - index {
+        1 => '+',
+        2 => '-',
+        _ => ' ',
+    }
+}
+
+/// hand the terminal over to a process group
+pub fn give_terminal(tty: RawFd, pgid: Pid) {
+    tcsetpgrp(tty, pgid).ok();
+}
+
+/// set up a new child of a job before it runs, only async-signal-safe calls here
+/// join the process group (`pgid` 0 to lead a new one), and take the terminal
+/// if it is in the foreground
+pub fn prepare_child(pgid: Pid, tty: Option<RawFd>) {
+    setpgid(Pid::from_raw(0), pgid).ok();
+    if let Some(tty) = tty {
+        tcsetpgrp(tty, getpgrp()).ok();
+    }
+}
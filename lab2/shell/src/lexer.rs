@@ -0,0 +1,461 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// a lexical token of a command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    /// a word, kept as written: quotes and escapes are removed on expansion
+    Word(String),
+    Op(Op),
+    /// digits right before a redirection, like 2 in `2>file`
+    IoNumber(i32),
+    /// the body of a here-document, in place of its delimiter word
+    HereDoc(HereDoc),
+    /// the expression of `(( expr ))` at the start of a command
+    Arith(String),
+}
+
+/// a here-document, read from the lines following the command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HereDoc {
+    pub delimiter: String,
+    pub body: String,
+    /// parameters are expanded in the body unless the delimiter is quoted
+    pub expand: bool,
+}
+
+/// operators recognized by the lexer, even when glued to words
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// `|`
+    Pipe,
+    /// `||`
+    OrIf,
+    /// `&`
+    Amp,
+    /// `&&`
+    AndIf,
+    /// `;`
+    Semi,
+    /// `;;`, ends an item of `case`
+    DSemi,
+    /// `<`
+    Less,
+    /// `>`
+    Great,
+    /// `>>`
+    DGreat,
+    /// `>|`, overwrites a file even with `set -C`
+    Clobber,
+    /// `<<`
+    DLess,
+    /// `<<-`, here-document with leading tabs stripped
+    DLessDash,
+    /// `<<<`, here-string
+    TLess,
+    /// `<&`
+    LessAnd,
+    /// `>&`
+    GreatAnd,
+    /// `<>`
+    LessGreat,
+    /// `&>`
+    AndGreat,
+    /// `&>>`
+    AndDGreat,
+    /// `(`
+    LParen,
+    /// `)`
+    RParen,
+    /// a line break, which ends a command like `;`
+    Newline,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(word) => f.write_str(word),
+            Token::Op(op) => op.fmt(f),
+            Token::IoNumber(fd) => fd.fmt(f),
+            Token::HereDoc(here_doc) => f.write_str(&here_doc.delimiter),
+            Token::Arith(expr) => write!(f, "(({}))", expr),
+        }
+    }
+}
+
+impl Op {
+    pub fn is_redirect(&self) -> bool {
+        matches!(
+            self,
+            Op::Less
+                | Op::Great
+                | Op::DGreat
+                | Op::Clobber
+                | Op::DLess
+                | Op::DLessDash
+                | Op::TLess
+                | Op::LessAnd
+                | Op::GreatAnd
+                | Op::LessGreat
+                | Op::AndGreat
+                | Op::AndDGreat
+        )
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Op::Pipe => "|",
+            Op::OrIf => "||",
+            Op::Amp => "&",
+            Op::AndIf => "&&",
+            Op::Semi => ";",
+            Op::DSemi => ";;",
+            Op::Less => "<",
+            Op::Great => ">",
+            Op::DGreat => ">>",
+            Op::Clobber => ">|",
+            Op::DLess => "<<",
+            Op::DLessDash => "<<-",
+            Op::TLess => "<<<",
+            Op::LessAnd => "<&",
+            Op::GreatAnd => ">&",
+            Op::LessGreat => "<>",
+            Op::AndGreat => "&>",
+            Op::AndDGreat => "&>>",
+            Op::LParen => "(",
+            Op::RParen => ")",
+            Op::Newline => "newline",
+        };
+        f.write_str(s)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LexError {
+    /// a quote, `$(` or `${` is opened but never closed
+    UnterminatedQuote(char),
+    /// a backslash at the very end of input
+    TrailingBackslash,
+    /// no line with the delimiter ends a here-document
+    UnterminatedHereDoc(String),
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexError::UnterminatedQuote(quote) => {
+                write!(
+                    f,
+                    "unexpected end of input while looking for matching `{}`",
+                    quote
+                )
+            }
+            LexError::TrailingBackslash => write!(f, "unexpected end of input after \\"),
+            LexError::UnterminatedHereDoc(delimiter) => {
+                write!(f, "here-document not delimited by `{}`", delimiter)
+            }
+        }
+    }
+}
+
+/// split a command line into words and operators
+pub fn tokenize(input: &str) -> Result<Vec<Token>, LexError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+    // the word after `<<` is a here-document delimiter, whether to strip tabs
+    let mut here_doc_op = None;
+    // here-documents whose bodies start after the next newline, (token index, strip tabs)
+    let mut pending_here_docs = Vec::new();
+    while let Some(&c) = chars.peek() {
+        if c == '\n' {
+            chars.next();
+            tokens.push(Token::Op(Op::Newline));
+            for (index, strip_tabs) in pending_here_docs.drain(..) {
+                if let Token::Word(word) = &tokens[index] {
+                    let here_doc = read_here_doc(&mut chars, word, strip_tabs)?;
+                    tokens[index] = Token::HereDoc(here_doc);
+                }
+            }
+        } else if c.is_whitespace() {
+            chars.next();
+        } else if c == '#' {
+            // a comment lasts until the end of line
+            while chars.next_if(|&c| c != '\n').is_some() {}
+        } else if c == '\\' && chars.clone().nth(1) == Some('\n') {
+            // line continuation between words
+            chars.nth(1);
+        } else if c == '(' && at_command_start(&tokens) && read_arith(&mut chars, &mut tokens) {
+            continue;
+        } else if is_operator_char(c) && !starts_process_substitution(&chars) {
+            let op = read_operator(&mut chars);
+            here_doc_op = match op {
+                Op::DLess => Some(false),
+                Op::DLessDash => Some(true),
+                _ => None,
+            };
+            tokens.push(Token::Op(op));
+        } else {
+            let word = read_word(&mut chars)?;
+            let io_number =
+                word.bytes().all(|b| b.is_ascii_digit()) && matches!(chars.peek(), Some('<' | '>'));
+            match word.parse() {
+                Ok(fd) if io_number => tokens.push(Token::IoNumber(fd)),
+                _ => {
+                    if let Some(strip_tabs) = here_doc_op.take() {
+                        pending_here_docs.push((tokens.len(), strip_tabs));
+                    }
+                    tokens.push(Token::Word(word));
+                }
+            }
+        }
+    }
+    if let Some((index, _)) = pending_here_docs.first() {
+        return Err(LexError::UnterminatedHereDoc(unquote(
+            &tokens[*index].to_string(),
+        )));
+    }
+    Ok(tokens)
+}
+
+fn is_operator_char(c: char) -> bool {
+    matches!(c, '|' | '&' | ';' | '<' | '>' | '(' | ')')
+}
+
+/// whether `<(` or `>(` of a process substitution is at the head of `chars`,
+/// which is part of a word rather than operators
+fn starts_process_substitution(chars: &Peekable<Chars>) -> bool {
+    let mut ahead = chars.clone();
+    matches!(ahead.next(), Some('<' | '>')) && ahead.next() == Some('(')
+}
+
+/// whether the next token starts a command, where `((` is arithmetic
+fn at_command_start(tokens: &[Token]) -> bool {
+    match tokens.last() {
+        None => true,
+        Some(Token::Op(op)) => *op != Op::RParen && !op.is_redirect(),
+        Some(Token::Word(word)) => matches!(
+            word.as_str(),
+            "then" | "do" | "else" | "elif" | "if" | "while" | "until" | "{" | "!"
+        ),
+        _ => false,
+    }
+}
+
+/// read `(( expr ))` as an arithmetic command, return false and read nothing
+/// if the parentheses do not close like that, like nested subshells `((cmd); cmd)`
+fn read_arith(chars: &mut Peekable<Chars>, tokens: &mut Vec<Token>) -> bool {
+    let mut ahead = chars.clone();
+    if ahead.next() != Some('(') || ahead.next() != Some('(') {
+        return false;
+    }
+    let mut expr = String::new();
+    let mut depth = 0;
+    while let Some(c) = ahead.next() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            ')' if ahead.next_if_eq(&')').is_some() => {
+                *chars = ahead;
+                tokens.push(Token::Arith(expr));
+                return true;
+            }
+            ')' => return false,
+            _ => (),
+        }
+        expr.push(c);
+    }
+    false
+}
+
+/// read the longest operator at the head of `chars`
+fn read_operator(chars: &mut Peekable<Chars>) -> Op {
+    let c = chars.next().unwrap();
+    let mut follow = |next: char| chars.next_if_eq(&next).is_some();
+    match c {
+        '|' if follow('|') => Op::OrIf,
+        '|' => Op::Pipe,
+        '&' if follow('&') => Op::AndIf,
+        '&' if follow('>') => match follow('>') {
+            true => Op::AndDGreat,
+            false => Op::AndGreat,
+        },
+        '&' => Op::Amp,
+        ';' if follow(';') => Op::DSemi,
+        ';' => Op::Semi,
+        '<' if follow('<') => match (follow('<'), follow('-')) {
+            (true, _) => Op::TLess,
+            (false, true) => Op::DLessDash,
+            (false, false) => Op::DLess,
+        },
+        '<' if follow('&') => Op::LessAnd,
+        '<' if follow('>') => Op::LessGreat,
+        '<' => Op::Less,
+        '>' if follow('>') => Op::DGreat,
+        '>' if follow('|') => Op::Clobber,
+        '>' if follow('&') => Op::GreatAnd,
+        '>' => Op::Great,
+        '(' => Op::LParen,
+        ')' => Op::RParen,
+        _ => unreachable!(),
+    }
+}
+
+/// read the lines of a here-document up to its delimiter
+fn read_here_doc(
+    chars: &mut Peekable<Chars>,
+    word: &str,
+    strip_tabs: bool,
+) -> Result<HereDoc, LexError> {
+    let delimiter = unquote(word);
+    let mut body = String::new();
+    loop {
+        if chars.peek().is_none() {
+            return Err(LexError::UnterminatedHereDoc(delimiter));
+        }
+        let line: String = chars.by_ref().take_while(|&c| c != '\n').collect();
+        let line = match strip_tabs {
+            true => line.trim_start_matches('\t'),
+            false => &line,
+        };
+        if line == delimiter {
+            break;
+        }
+        body.push_str(line);
+        body.push('\n');
+    }
+    Ok(HereDoc {
+        expand: !word.contains(['\'', '"', '\\']),
+        delimiter,
+        body,
+    })
+}
+
+/// read a word up to the next unquoted blank or operator, quotes kept
+fn read_word(chars: &mut Peekable<Chars>) -> Result<String, LexError> {
+    let mut word = String::new();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() || (is_operator_char(c) && !starts_process_substitution(chars)) {
+            break;
+        }
+        chars.next();
+        if c == '\\' && chars.next_if_eq(&'\n').is_some() {
+            // line continuation inside a word
+            continue;
+        }
+        word.push(c);
+        match c {
+            '\\' => word.push(chars.next().ok_or(LexError::TrailingBackslash)?),
+            '\'' => loop {
+                let c = chars.next().ok_or(LexError::UnterminatedQuote('\''))?;
+                word.push(c);
+                if c == '\'' {
+                    break;
+                }
+            },
+            '"' => read_double_quoted(chars, &mut word)?,
+            '$' | '<' | '>' => read_substitution(chars, &mut word)?,
+            '`' => read_backquoted(chars, &mut word)?,
+            _ => (),
+        }
+    }
+    Ok(word)
+}
+
+/// read the rest of a double-quoted string after `"`
+fn read_double_quoted(chars: &mut Peekable<Chars>, word: &mut String) -> Result<(), LexError> {
+    loop {
+        let c = chars.next().ok_or(LexError::UnterminatedQuote('"'))?;
+        word.push(c);
+        match c {
+            '"' => return Ok(()),
+            '\\' => word.push(chars.next().ok_or(LexError::UnterminatedQuote('"'))?),
+            '$' => read_substitution(chars, word)?,
+            '`' => read_backquoted(chars, word)?,
+            _ => (),
+        }
+    }
+}
+
+/// read `(...)` or `{...}` after `$`, or `(...)` after `<` or `>`, up to the
+/// matching close, nested ones included
+fn read_substitution(chars: &mut Peekable<Chars>, word: &mut String) -> Result<(), LexError> {
+    let (open, close) = match chars.peek() {
+        Some('(') => ('(', ')'),
+        Some('{') => ('{', '}'),
+        _ => return Ok(()),
+    };
+    let mut depth = 0;
+    loop {
+        let c = chars.next().ok_or(LexError::UnterminatedQuote(close))?;
+        word.push(c);
+        match c {
+            '\\' => word.push(chars.next().ok_or(LexError::TrailingBackslash)?),
+            '\'' => loop {
+                let c = chars.next().ok_or(LexError::UnterminatedQuote('\''))?;
+                word.push(c);
+                if c == '\'' {
+                    break;
+                }
+            },
+            '"' => read_double_quoted(chars, word)?,
+            '`' => read_backquoted(chars, word)?,
+            '$' => read_substitution(chars, word)?,
+            c if c == open => depth += 1,
+            c if c == close => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(());
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// read the rest of a command substitution after `` ` ``
+fn read_backquoted(chars: &mut Peekable<Chars>, word: &mut String) -> Result<(), LexError> {
+    loop {
+        let c = chars.next().ok_or(LexError::UnterminatedQuote('`'))?;
+        word.push(c);
+        match c {
+            '`' => return Ok(()),
+            '\\' => word.push(chars.next().ok_or(LexError::UnterminatedQuote('`'))?),
+            _ => (),
+        }
+    }
+}
+
+/// remove quotes and backslash escapes from a word
+pub fn unquote(word: &str) -> String {
+    let mut result = String::new();
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => result.extend(chars.next()),
+            '\'' => result.extend(chars.by_ref().take_while(|&c| c != '\'')),
+            '"' => {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        // inside double quotes backslash only escapes these
+                        '\\' => match chars.next() {
+                            Some(c @ ('$' | '`' | '"' | '\\')) => result.push(c),
+                            Some('\n') => (),
+                            Some(c) => {
+                                result.push('\\');
+                                result.push(c)
+                            }
+                            None => result.push('\\'),
+                        },
+                        _ => result.push(c),
+                    }
+                }
+            }
+            _ => result.push(c),
+        }
+    }
+    result
+}
@@ -1,210 +1,270 @@
+pub mod arith;
+pub mod bang;
+pub mod builtin;
+pub mod complete;
+pub mod condition;
+pub mod dirs;
+pub mod editor;
+pub mod exec;
+pub mod expand;
+pub mod glob;
 pub mod history;
+pub mod jobs;
+pub mod lexer;
+pub mod parser;
+pub mod prompt;
+pub mod redirect;
+pub mod resolve;
+pub mod shell;
+pub mod signals;
+pub mod vars;
 
+use editor::Editor;
 use history::History;
-use nix::sys::signal::{signal, SigHandler, Signal};
-use nix::sys::wait::wait;
-use std::cmp::min;
+use nix::fcntl::{fcntl, FcntlArg};
+use nix::sys::signal::{killpg, Signal};
+use nix::unistd::{getpgrp, isatty, setpgid, tcgetpgrp, tcsetpgrp, Pid};
+use shell::{Control, Shell};
+use signals::Disposition;
 use std::env;
-use std::fs::{File, OpenOptions};
+use std::fs;
 use std::io::{self, stdin, Write};
-use std::path::Path;
-use std::process::{exit, Command, Stdio};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::os::unix::io::RawFd;
+use std::process::exit;
 
-const COLOR_GREEN: &str = "\x1B[38;5;10m";
 const COLOR_YELLOW: &str = "\x1B[38;5;11m";
 const CLEAR_COLOR: &str = "\x1B[0m";
 
-/// INPUTING indicates whether the shell is waiting for user input
-static INPUTING: AtomicBool = AtomicBool::new(true);
-extern "C" fn handle_sigint(_: libc::c_int) {
-    println!();
-    if INPUTING.load(Ordering::Relaxed) {
-        print_prompt().expect("error print prompt")
+/// take the terminal for job control, return the fd of the terminal
+fn init_job_control() -> Option<RawFd> {
+    if !isatty(0).unwrap_or(false) {
+        return None;
+    }
+    // wait until running in the foreground
+    while tcgetpgrp(0).ok()? != getpgrp() {
+        killpg(getpgrp(), Signal::SIGTTIN).ok()?;
+    }
+    for sig in [Signal::SIGTSTP, Signal::SIGTTIN, Signal::SIGTTOU] {
+        signals::set(sig, Disposition::Ignore);
     }
+    setpgid(Pid::from_raw(0), Pid::from_raw(0)).ok();
+    tcsetpgrp(0, getpgrp()).ok()?;
+    // a copy kept open, as children change stdin before taking the terminal
+    fcntl(0, FcntlArg::F_DUPFD_CLOEXEC(10)).ok()
 }
 
 fn main() -> ! {
-    unsafe { signal(Signal::SIGINT, SigHandler::Handler(handle_sigint)) }
-        .expect("Error changing SIGINT handler");
+    let args: Vec<String> = env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("-c") => {
+            let command = args.get(2).unwrap_or_else(|| {
+                eprintln!("llysh: -c: option requires an argument");
+                exit(2)
+            });
+            // `llysh -c command name args...` sets `$0` to name
+            let positional = match args.get(3) {
+                Some(_) => args[3..].to_vec(),
+                None => args[..1].to_vec(),
+            };
+            run_script(command, positional)
+        }
+        Some(script) => match fs::read_to_string(script) {
+            Ok(source) => run_script(&source, args[1..].to_vec()),
+            Err(err) => {
+                eprintln!("llysh: {}: {}", script, builtin::strerror(&err));
+                exit(127)
+            }
+        },
+        None => run_interactive(args),
+    }
+}
+
+/// run a script or `-c` command without prompt and history
+/// exit with the status of the last command
+fn run_script(source: &str, args: Vec<String>) -> ! {
+    signals::init();
+    let mut shell = Shell::new(History::default(), args);
+    let status = exec::run_source(source, &mut shell);
+    exec::exit_shell(status, &mut shell)
+}
+
+/// read commands from stdin, with prompt and history
+fn run_interactive(args: Vec<String>) -> ! {
+    // SIGINT cancels the line or leaves the commands being run, SIGCHLD
+    // makes the jobs reaped before the prompt
+    signals::init();
+    signals::set(Signal::SIGINT, Disposition::Catch);
+    signals::set(Signal::SIGCHLD, Disposition::Catch);
+    signals::set(Signal::SIGQUIT, Disposition::Ignore);
+    signals::set(Signal::SIGTERM, Disposition::Ignore);
 
     // open or create history file
     let history_file_name =
         env::var("HOME").unwrap_or_else(|_| "/tmp".to_string()) + "/.llysh_history";
-    let mut history = History::new(history_file_name).expect("Cannot open history file!");
+    let history = History::new(history_file_name.clone()).unwrap_or_else(|err| {
+        eprintln!("llysh: {}: {}", history_file_name, builtin::strerror(&err));
+        History::default()
+    });
+    let mut shell = Shell::new(history, args);
+    shell.interactive = true;
+    shell.tty = init_job_control();
+    let mut editor = shell.tty.map(|_| Editor::default());
+    for (name, value) in [
+        ("PS1", prompt::DEFAULT_PS1),
+        ("PS2", prompt::DEFAULT_PS2),
+        ("HISTSIZE", history::DEFAULT_SIZE),
+        ("HISTFILESIZE", history::DEFAULT_FILE_SIZE),
+        ("HISTCONTROL", history::DEFAULT_CONTROL),
+    ] {
+        if shell.vars.get_var(name).is_none() {
+            shell.vars.set(name, value).ok();
+        }
+    }
+    source_rc(&mut shell);
 
     loop {
-        // prompt message
-        INPUTING.store(true, Ordering::Relaxed);
-        print_prompt().expect("error print prompt");
-
-        // read line
-        let mut command = String::new();
-        // EOF handling
-        if let Ok(0) = stdin().read_line(&mut command) {
-            println!();
-            exit(0)
+        // report background jobs finished or stopped
+        if signals::take_signal(Signal::SIGCHLD) {
+            shell.jobs.reap();
+            if let Some(action) = shell.traps.get(&(Signal::SIGCHLD as i32)).cloned() {
+                exec::run_trap(&action, &mut shell);
+            }
         }
+        // the traps of signals caught while reading the last line
+        exec::handle_signals(&mut shell);
+        shell.control = None;
 
-        // if the actuall command is from history
-        let command = replace_from_history(&command, &history).unwrap_or(command);
-        if command.trim() != history.last().cloned().unwrap_or_default() {
-            history.push(&command);
+        // the hook before each prompt, which leaves `$?` as it is
+        if let Some(hook) = shell.vars.get("PROMPT_COMMAND").map(str::to_string) {
+            let status = shell.status;
+            exec::run_source(&hook, &mut shell);
+            shell.status = status;
+            shell.control = None;
         }
 
-        // seperate commands by pipes
-        let tokens: Vec<String> = get_tokens(command);
-        let commands: Vec<&[String]> = tokens.split(|token| token == "|").collect();
-
-        // execute commands and concat their stdios with pipes
-        INPUTING.store(false, Ordering::Relaxed);
-        let mut child_stdin = Stdio::inherit();
-        let mut child_stdout = Stdio::piped();
-        let mut command_iter = commands.iter().peekable();
-        while let Some(command) = command_iter.next() {
-            let last = command_iter.peek().is_none();
-            let cur_child_stdout = if last { Stdio::inherit() } else { child_stdout };
-            match execute_command(command, last, &history, child_stdin, cur_child_stdout) {
-                Some((stdin, stdout)) => {
-                    (child_stdin, child_stdout) = (stdin, stdout);
+        // prompt message and read lines, until the command is complete
+        let mut command = String::new();
+        let mut cancelled = false;
+        loop {
+            let prompt = prompt(&shell, !command.is_empty());
+            match read_line(editor.as_mut(), &prompt, &shell) {
+                Ok(Some(line)) => {
+                    if !command.is_empty() {
+                        command.push('\n');
+                    }
+                    command += line.strip_suffix('\n').unwrap_or(&line);
+                }
+                // Ctrl-C drops all the lines read
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {
+                    cancelled = true;
+                    break;
                 }
-                _ => break,
+                // EOF handling
+                Ok(None) | Err(_) if command.is_empty() => {
+                    println!();
+                    exec::exit_shell(shell.status, &mut shell)
+                }
+                // run as it is, to report what is missing
+                Ok(None) | Err(_) => break,
+            }
+            if !exec::is_incomplete(&command, &shell) {
+                break;
             }
         }
+        if cancelled {
+            continue;
+        }
 
-        // wait for all childs
-        while matches!(wait(), Ok(_)) {}
-    }
-}
-
-/// execute one command, may be with redirection, like "ls > out"
-/// last: if the command is the last one (in pipe)
-/// stdin and stdout are suggested by pipe. redirections are prior
-fn execute_command(
-    command: &[String],
-    last: bool,
-    history: &History,
-    mut stdin: Stdio,
-    mut stdout: Stdio,
-) -> Option<(Stdio, Stdio)> {
-    let mut last_command_index = command.len();
-    let mut redirect =
-        |token: &str, stdio: &mut Stdio, read: bool, write: bool, append: bool| -> Option<()> {
-            if let Some(index) = command.iter().position(|_token| _token == token) {
-                let file_path = command.get(index + 1)?;
-                if File::open(file_path).is_err() && (write || append) {
-                    File::create(file_path).ok()?;
-                }
-                let file = OpenOptions::new()
-                    .read(read)
-                    .write(write)
-                    .append(append)
-                    .open(file_path)
-                    .ok()?;
-                *stdio = Stdio::from(file);
-                last_command_index = min(last_command_index, index)
+        // references to earlier commands, like `!!`
+        let (command, print_only) = match expand_history(&command, &shell.history) {
+            Ok(expanded) => expanded.unwrap_or((command, false)),
+            Err(err) => {
+                eprintln!("llysh: {}", err);
+                continue;
             }
-            Some(())
         };
-    redirect("<", &mut stdin, true, false, false)?;
-    redirect(">", &mut stdout, false, true, false)?;
-    redirect(">>", &mut stdout, false, true, true)?;
-
-    let mut token_iter = command[0..last_command_index].iter();
-    let prog = token_iter.next().cloned().unwrap_or_default();
-    let args: Vec<String> = token_iter.map(|s| s.to_owned()).collect();
-    if let "" | "history" | "cd" | "export" | "exit" = prog.as_str() {
-        if do_built_in(&prog, &args, history).is_none() {
-            println!("Error occured in built-in command {}", &prog)
-        }
-        return None;
-    }
-    let mut child = Command::new(&prog)
-        .args(&args)
-        .stdin(stdin)
-        .stdout(stdout)
-        .spawn()
-        .map_err(|_| println!("{}: command not found", &prog))
-        .ok()?;
-    (!last).then(|| Some((Stdio::from(child.stdout.take()?), Stdio::piped())))?
-}
-
-/// built-in commands
-fn do_built_in(prog: &str, args: &Vec<String>, history: &History) -> Option<()> {
-    match prog {
-        "history" => {
-            let number = args.get(0)?.parse::<usize>().ok()?;
-            let history_size = history.size();
-            for i in (0..min(number, history_size)).rev() {
-                println!("{:5}  {}", history_size - i, history.rget(i).unwrap())
+        let cwd = dirs::current(&shell);
+        let vars = &shell.vars;
+        let history = &mut shell.history;
+        history.configure(
+            vars.get("HISTSIZE"),
+            vars.get("HISTFILESIZE"),
+            vars.get("HISTCONTROL"),
+        );
+        history.push(&command, Some(cwd));
+        if print_only {
+            if let Err(err) = history.finish(0) {
+                eprintln!("llysh: history: {}", builtin::strerror(&err));
             }
+            continue;
         }
-        "cd" => {
-            let home = env::var("HOME").unwrap_or_default();
-            let dir = args.get(0).cloned().unwrap_or(home);
-            env::set_current_dir(dir).ok()?
+
+        exec::run_source(&command, &mut shell);
+        if shell.control == Some(Control::Interrupt) {
+            shell.status = 128 + Signal::SIGINT as i32;
         }
-        "export" => {
-            for arg in args {
-                let mut assign = arg.split('=');
-                let key = assign.next()?;
-                let value = assign.next()?;
-                env::set_var(key, value);
-            }
+        if let Err(err) = shell.history.finish(shell.status) {
+            eprintln!("llysh: history: {}", builtin::strerror(&err));
         }
-        "exit" => {
-            exit(0);
+        // an interrupted job stops the commands of the line, and only them
+        shell.control = None;
+    }
+}
+
+/// run `~/.llyshrc` in the shell if it exists
+fn source_rc(shell: &mut Shell) {
+    let path = shell.vars.get("HOME").unwrap_or_default().to_string() + "/.llyshrc";
+    match fs::read_to_string(&path) {
+        Ok(source) => {
+            exec::run_file(&source, &[], shell);
+            shell.control = None;
         }
-        _ => (),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+        Err(err) => eprintln!("llysh: {}: {}", path, builtin::strerror(&err)),
     }
-    Some(())
 }
 
-/// get tokens for a command
-fn get_tokens(command: String) -> Vec<String> {
-    command
-        .split_whitespace()
-        .map(|token| {
-            if token.starts_with('$') {
-                let key = token.strip_prefix('$').unwrap();
-                env::var(key).unwrap_or_default()
-            } else if token == "~" || (token.starts_with("~/")) {
-                let home = env::var("HOME").unwrap_or_default();
-                home + token.strip_prefix('~').unwrap()
-            } else {
-                token.to_string()
+/// read a line with the line editor on a terminal, or from stdin otherwise
+fn read_line(
+    editor: Option<&mut Editor>,
+    prompt: &str,
+    shell: &Shell,
+) -> io::Result<Option<String>> {
+    match editor {
+        Some(editor) => editor.read_line(prompt, &shell.history, &|line| {
+            complete::complete(line, shell)
+        }),
+        None => {
+            print!("{}", prompt);
+            io::stdout().flush().ok();
+            let mut line = String::new();
+            match stdin().read_line(&mut line)? {
+                0 => Ok(None),
+                _ => Ok(Some(line)),
             }
-        })
-        .collect()
+        }
+    }
 }
 
-/// print prompt message
-fn print_prompt() -> Option<()> {
-    let cwd = env::current_dir().ok()?;
-    let home = env::var("HOME").unwrap_or_default();
-    let path = if cwd == Path::new(&home) {
-        '~'.to_string()
-    } else if !home.is_empty() && cwd.starts_with(&home) {
-        "~/".to_string() + cwd.strip_prefix(&home).ok()?.to_str()?
-    } else {
-        cwd.to_str()?.to_string()
-    };
-    print!("{}{}{}> ", COLOR_GREEN, &path, CLEAR_COLOR);
-    io::stdout().flush().ok()?;
-    Some(())
+/// the prompt message rendered from `PS1`, or from `PS2` on the following
+/// lines of an unfinished command
+fn prompt(shell: &Shell, continued: bool) -> String {
+    let name = if continued { "PS2" } else { "PS1" };
+    prompt::render(shell.vars.get(name).unwrap_or_default(), shell)
 }
 
-/// return the origin command if available
-fn replace_from_history(command: &str, history: &History) -> Option<String> {
-    let arg = command
-        .starts_with('!')
-        .then(|| command.strip_prefix('!').unwrap().trim())?;
-    let command = if arg.starts_with('!') {
-        history.last().cloned()?
-    } else {
-        let number = arg.parse::<usize>().ok()?;
-        history.get(number).cloned()?
+/// expand the history references in a command and echo it if any, return
+/// None if there are none, and whether to only print it for `:p`
+fn expand_history(command: &str, history: &History) -> Result<Option<(String, bool)>, String> {
+    let expansion = match bang::expand(command, history)? {
+        Some(expansion) => expansion,
+        None => return Ok(None),
     };
-    println!("> {}{}{}", COLOR_YELLOW, &command, CLEAR_COLOR);
-    Some(command)
+    println!(
+        "> {}{}{}",
+        COLOR_YELLOW,
+        expansion.line.trim_end(),
+        CLEAR_COLOR
+    );
+    Ok(Some((expansion.line, expansion.print_only)))
 }
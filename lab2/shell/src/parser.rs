@@ -0,0 +1,751 @@
+use crate::lexer::{self, HereDoc, Op, Token};
+use crate::vars::{is_name, split_assignment};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::iter::Peekable;
+use std::mem;
+use std::vec::IntoIter;
+
+/// and-or lists run one after another, like `a; b`
+#[derive(Debug, Clone, Default)]
+pub struct List(pub Vec<AndOr>);
+
+/// pipelines chained by `&&` and `||`
+#[derive(Debug, Clone)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+    /// ended by `&`, run as a background job
+    pub background: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connector {
+    /// `&&`: run only if the previous pipeline succeeded
+    And,
+    /// `||`: run only if the previous pipeline failed
+    Or,
+}
+
+/// commands connected by `|`
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    pub commands: Vec<Command>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Command {
+    Simple(SimpleCommand),
+    /// `( list )`, run in a child process
+    Subshell(List, Vec<Redirect>),
+    /// `{ list; }`, run in the current shell
+    Group(List, Vec<Redirect>),
+    If(If, Vec<Redirect>),
+    Loop(Loop, Vec<Redirect>),
+    For(For, Vec<Redirect>),
+    Case(Case, Vec<Redirect>),
+    /// `(( expr ))`, succeeds if the expression is not 0
+    Arith(String, Vec<Redirect>),
+    /// `name() body`, defines a function run by its name like a command
+    Function(String, Box<Command>),
+}
+
+/// `if list; then list; [elif list; then list;]... [else list;] fi`
+#[derive(Debug, Clone)]
+pub struct If {
+    /// conditions and the lists run if they succeed, tried in order
+    pub branches: Vec<(List, List)>,
+    pub otherwise: Option<List>,
+}
+
+/// `while list; do list; done`, or `until`
+#[derive(Debug, Clone)]
+pub struct Loop {
+    /// run the body while the condition fails instead
+    pub until: bool,
+    pub condition: List,
+    pub body: List,
+}
+
+/// `for name [in word ...]; do list; done`
+#[derive(Debug, Clone)]
+pub struct For {
+    pub name: String,
+    /// None without `in`, to loop over the positional parameters
+    pub words: Option<Vec<String>>,
+    pub body: List,
+}
+
+/// `case word in [(]pattern [| pattern]...) list;; ... esac`
+#[derive(Debug, Clone)]
+pub struct Case {
+    pub word: String,
+    pub items: Vec<CaseItem>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CaseItem {
+    pub patterns: Vec<String>,
+    pub body: List,
+}
+
+/// assignments, words and redirections, none expanded yet
+#[derive(Debug, Clone, Default)]
+pub struct SimpleCommand {
+    /// `NAME=value` before the first word
+    pub assignments: Vec<String>,
+    pub words: Vec<String>,
+    pub redirects: Vec<Redirect>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Redirect {
+    /// the fd given before the operator, like 2 in `2>file`
+    pub fd: Option<i32>,
+    pub op: Op,
+    pub target: RedirectTarget,
+}
+
+#[derive(Debug, Clone)]
+pub enum RedirectTarget {
+    /// a file name, an fd for `>&` and `<&`, or a here-string, not yet expanded
+    Word(String),
+    HereDoc(HereDoc),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    Unexpected(Token),
+    UnexpectedEof,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Unexpected(token) => {
+                write!(f, "syntax error near unexpected token `{}`", token)
+            }
+            ParseError::UnexpectedEof => write!(f, "syntax error: unexpected end of input"),
+        }
+    }
+}
+
+/// words that end the list before them, when they start a command
+const LIST_ENDS: [&str; 8] = ["}", "then", "elif", "else", "fi", "do", "done", "esac"];
+
+impl Command {
+    /// redirections of the command as a whole
+    pub fn redirects(&self) -> &[Redirect] {
+        match self {
+            Command::Simple(command) => &command.redirects,
+            Command::Subshell(_, redirects)
+            | Command::Group(_, redirects)
+            | Command::If(_, redirects)
+            | Command::Loop(_, redirects)
+            | Command::For(_, redirects)
+            | Command::Case(_, redirects)
+            | Command::Arith(_, redirects) => redirects,
+            Command::Function(..) => &[],
+        }
+    }
+}
+
+/// a list followed by `;`, unless it ends with `&` already
+fn terminated(list: &List) -> String {
+    match list.0.last() {
+        Some(and_or) if and_or.background => list.to_string(),
+        _ => format!("{};", list),
+    }
+}
+
+impl fmt::Display for List {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, and_or) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(if self.0[i - 1].background { " " } else { "; " })?;
+            }
+            write!(f, "{}", and_or)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for AndOr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.first)?;
+        for (connector, pipeline) in &self.rest {
+            let connector = match connector {
+                Connector::And => "&&",
+                Connector::Or => "||",
+            };
+            write!(f, " {} {}", connector, pipeline)?;
+        }
+        if self.background {
+            f.write_str(" &")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, command) in self.commands.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" | ")?;
+            }
+            write!(f, "{}", command)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = match self {
+            Command::Simple(command) => {
+                let words = command.assignments.iter().chain(&command.words);
+                words.cloned().collect()
+            }
+            Command::Subshell(list, _) => vec![format!("({})", list)],
+            Command::Group(list, _) => vec![format!("{{ {} }}", terminated(list))],
+            Command::If(clause, _) => {
+                let mut text = String::new();
+                for (i, (condition, body)) in clause.branches.iter().enumerate() {
+                    let keyword = if i == 0 { "if" } else { " elif" };
+                    text += &format!(
+                        "{} {} then {}",
+                        keyword,
+                        terminated(condition),
+                        terminated(body)
+                    );
+                }
+                if let Some(otherwise) = &clause.otherwise {
+                    text += &format!(" else {}", terminated(otherwise));
+                }
+                vec![text + " fi"]
+            }
+            Command::Loop(clause, _) => {
+                let keyword = if clause.until { "until" } else { "while" };
+                let condition = terminated(&clause.condition);
+                vec![format!(
+                    "{} {} do {} done",
+                    keyword,
+                    condition,
+                    terminated(&clause.body)
+                )]
+            }
+            Command::For(clause, _) => {
+                let words = match &clause.words {
+                    Some(words) => format!(" in {};", words.join(" ")),
+                    None => ";".to_string(),
+                };
+                let body = terminated(&clause.body);
+                vec![format!("for {}{} do {} done", clause.name, words, body)]
+            }
+            Command::Case(clause, _) => {
+                let mut text = format!("case {} in", clause.word);
+                for item in &clause.items {
+                    text += &format!(" {}) {};;", item.patterns.join(" | "), item.body);
+                }
+                vec![text + " esac"]
+            }
+            Command::Arith(expr, _) => vec![format!("(({}))", expr)],
+            Command::Function(name, body) => return write!(f, "{}() {}", name, body),
+        };
+        parts.extend(self.redirects().iter().map(|redirect| redirect.to_string()));
+        f.write_str(&parts.join(" "))
+    }
+}
+
+impl fmt::Display for Redirect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(fd) = self.fd {
+            write!(f, "{}", fd)?;
+        }
+        match &self.target {
+            RedirectTarget::Word(word) => write!(f, "{}{}", self.op, word),
+            RedirectTarget::HereDoc(here_doc) => write!(f, "{}{}", self.op, here_doc.delimiter),
+        }
+    }
+}
+
+/// parse a whole command line into a list, expanding aliases on the way
+pub fn parse(tokens: Vec<Token>, aliases: &BTreeMap<String, String>) -> Result<List, ParseError> {
+    let mut parser = Parser {
+        tokens: tokens.into_iter().peekable(),
+        aliases,
+    };
+    let list = parser.list()?;
+    match parser.tokens.next() {
+        Some(token) => Err(ParseError::Unexpected(token)),
+        None => Ok(list),
+    }
+}
+
+/// whether source text ends in the middle of a command, like in quotes or in
+/// `if` without `fi`, so that more lines are needed
+pub fn is_incomplete(source: &str, aliases: &BTreeMap<String, String>) -> bool {
+    match lexer::tokenize(source) {
+        Ok(tokens) => matches!(parse(tokens, aliases), Err(ParseError::UnexpectedEof)),
+        Err(_) => true,
+    }
+}
+
+/// the lines of a source parsed one at a time, so that aliases defined by a
+/// line apply to the lines after it
+pub struct Lines {
+    tokens: Peekable<IntoIter<Token>>,
+}
+
+impl Lines {
+    pub fn new(tokens: Vec<Token>) -> Lines {
+        Lines {
+            tokens: tokens.into_iter().peekable(),
+        }
+    }
+
+    /// parse the commands of the next line with the aliases there are now,
+    /// None at the end of the source
+    pub fn next_line(
+        &mut self,
+        aliases: &BTreeMap<String, String>,
+    ) -> Option<Result<List, ParseError>> {
+        let mut parser = Parser {
+            tokens: mem::replace(&mut self.tokens, Vec::new().into_iter().peekable()),
+            aliases,
+        };
+        parser.skip_newlines();
+        let line = parser.tokens.peek().is_some().then(|| parser.line());
+        self.tokens = parser.tokens;
+        line
+    }
+}
+
+struct Parser<'a> {
+    tokens: Peekable<IntoIter<Token>>,
+    aliases: &'a BTreeMap<String, String>,
+}
+
+impl Parser<'_> {
+    fn peek_op(&mut self) -> Option<Op> {
+        match self.tokens.peek() {
+            Some(Token::Op(op)) => Some(*op),
+            _ => None,
+        }
+    }
+
+    fn peek_word(&mut self, word: &str) -> bool {
+        matches!(self.tokens.peek(), Some(Token::Word(w)) if w == word)
+    }
+
+    /// the next token, which must be a word
+    fn next_word(&mut self) -> Result<String, ParseError> {
+        match self.tokens.next() {
+            Some(Token::Word(word)) => Ok(word),
+            Some(token) => Err(ParseError::Unexpected(token)),
+            None => Err(ParseError::UnexpectedEof),
+        }
+    }
+
+    fn expect_word(&mut self, word: &str) -> Result<(), ParseError> {
+        self.expect(Token::Word(word.to_string()))
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), ParseError> {
+        match self.tokens.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(ParseError::Unexpected(token)),
+            None => Err(ParseError::UnexpectedEof),
+        }
+    }
+
+    /// whether the list ends here: end of input, `)`, `;;`, or a word like `}` or `fi`
+    fn at_list_end(&mut self) -> bool {
+        match self.tokens.peek() {
+            None | Some(Token::Op(Op::RParen | Op::DSemi)) => true,
+            Some(Token::Word(word)) => LIST_ENDS.contains(&word.as_str()),
+            _ => false,
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek_op() == Some(Op::Newline) {
+            self.tokens.next();
+        }
+    }
+
+    fn list(&mut self) -> Result<List, ParseError> {
+        let mut list = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.at_list_end() {
+                break;
+            }
+            let mut and_or = self.and_or()?;
+            match self.peek_op() {
+                Some(Op::Semi | Op::Newline) => (),
+                Some(Op::Amp) => and_or.background = true,
+                _ => {
+                    list.push(and_or);
+                    break;
+                }
+            }
+            self.tokens.next();
+            list.push(and_or);
+        }
+        Ok(List(list))
+    }
+
+    /// the commands up to the next newline outside of a compound command,
+    /// which is left for the next line
+    fn line(&mut self) -> Result<List, ParseError> {
+        let mut list = Vec::new();
+        while !self.at_list_end() {
+            let mut and_or = self.and_or()?;
+            match self.peek_op() {
+                Some(Op::Semi) => (),
+                Some(Op::Amp) => and_or.background = true,
+                _ => {
+                    list.push(and_or);
+                    break;
+                }
+            }
+            self.tokens.next();
+            list.push(and_or);
+            if self.peek_op() == Some(Op::Newline) {
+                break;
+            }
+        }
+        match self.tokens.peek() {
+            None | Some(Token::Op(Op::Newline)) => Ok(List(list)),
+            Some(token) => Err(ParseError::Unexpected(token.clone())),
+        }
+    }
+
+    fn and_or(&mut self) -> Result<AndOr, ParseError> {
+        let first = self.pipeline()?;
+        let mut rest = Vec::new();
+        loop {
+            let connector = match self.peek_op() {
+                Some(Op::AndIf) => Connector::And,
+                Some(Op::OrIf) => Connector::Or,
+                _ => break,
+            };
+            self.tokens.next();
+            self.skip_newlines();
+            rest.push((connector, self.pipeline()?));
+        }
+        Ok(AndOr {
+            first,
+            rest,
+            background: false,
+        })
+    }
+
+    fn pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let mut commands = vec![self.command()?];
+        while self.peek_op() == Some(Op::Pipe) {
+            self.tokens.next();
+            self.skip_newlines();
+            commands.push(self.command()?);
+        }
+        Ok(Pipeline { commands })
+    }
+
+    /// replace an alias at the start of a command with the tokens of its value,
+    /// again and again, but not an alias already replaced there like `ls` in
+    /// `alias ls='ls -F'`
+    fn expand_alias(&mut self) {
+        let mut expanded = Vec::new();
+        loop {
+            let value = match self.tokens.peek() {
+                Some(Token::Word(word)) if !expanded.contains(word) => match self.aliases.get(word)
+                {
+                    Some(value) => value,
+                    None => return,
+                },
+                _ => return,
+            };
+            let tokens = match lexer::tokenize(value) {
+                Ok(tokens) => tokens,
+                Err(_) => return,
+            };
+            if let Some(Token::Word(word)) = self.tokens.next() {
+                expanded.push(word);
+            }
+            let rest: Vec<Token> = tokens.into_iter().chain(self.tokens.by_ref()).collect();
+            self.tokens = rest.into_iter().peekable();
+        }
+    }
+
+    /// whether a function definition starts here, like `name()` or `function name`
+    fn at_function(&mut self) -> bool {
+        let mut ahead = self.tokens.clone();
+        match ahead.next() {
+            Some(Token::Word(word)) if word == "function" => {
+                matches!(ahead.next(), Some(Token::Word(name)) if is_name(&name))
+            }
+            Some(Token::Word(name)) if is_name(&name) => {
+                ahead.next() == Some(Token::Op(Op::LParen))
+                    && ahead.next() == Some(Token::Op(Op::RParen))
+            }
+            _ => false,
+        }
+    }
+
+    fn command(&mut self) -> Result<Command, ParseError> {
+        self.expand_alias();
+        if self.at_function() {
+            return self.function();
+        }
+        if let Some(Token::Arith(expr)) = self.tokens.peek() {
+            let expr = expr.clone();
+            self.tokens.next();
+            return Ok(Command::Arith(expr, self.redirects()?));
+        }
+        if self.peek_op() == Some(Op::LParen) {
+            self.tokens.next();
+            let list = self.compound_body()?;
+            self.expect(Token::Op(Op::RParen))?;
+            return Ok(Command::Subshell(list, self.redirects()?));
+        }
+        let keyword = match self.tokens.peek() {
+            Some(Token::Word(word)) => word.clone(),
+            _ => String::new(),
+        };
+        match keyword.as_str() {
+            "{" => {
+                self.tokens.next();
+                let list = self.compound_body()?;
+                self.expect_word("}")?;
+                Ok(Command::Group(list, self.redirects()?))
+            }
+            "if" => {
+                let clause = self.if_clause()?;
+                Ok(Command::If(clause, self.redirects()?))
+            }
+            "while" | "until" => {
+                let clause = self.loop_clause()?;
+                Ok(Command::Loop(clause, self.redirects()?))
+            }
+            "for" => {
+                let clause = self.for_clause()?;
+                Ok(Command::For(clause, self.redirects()?))
+            }
+            "case" => {
+                let clause = self.case_clause()?;
+                Ok(Command::Case(clause, self.redirects()?))
+            }
+            _ => self.simple_command().map(Command::Simple),
+        }
+    }
+
+    fn if_clause(&mut self) -> Result<If, ParseError> {
+        let mut branches = Vec::new();
+        let mut otherwise = None;
+        // `if` and then each `elif`
+        self.tokens.next();
+        loop {
+            let condition = self.compound_body()?;
+            self.expect_word("then")?;
+            branches.push((condition, self.compound_body()?));
+            if self.peek_word("elif") {
+                self.tokens.next();
+            } else {
+                break;
+            }
+        }
+        if self.peek_word("else") {
+            self.tokens.next();
+            otherwise = Some(self.compound_body()?);
+        }
+        self.expect_word("fi")?;
+        Ok(If {
+            branches,
+            otherwise,
+        })
+    }
+
+    fn loop_clause(&mut self) -> Result<Loop, ParseError> {
+        let until = self.next_word()? == "until";
+        let condition = self.compound_body()?;
+        let body = self.do_group()?;
+        Ok(Loop {
+            until,
+            condition,
+            body,
+        })
+    }
+
+    fn for_clause(&mut self) -> Result<For, ParseError> {
+        self.tokens.next();
+        let name = match self.tokens.next() {
+            Some(Token::Word(name)) if is_name(&name) => name,
+            Some(token) => return Err(ParseError::Unexpected(token)),
+            None => return Err(ParseError::UnexpectedEof),
+        };
+        if self.peek_op() == Some(Op::Semi) {
+            self.tokens.next();
+        }
+        self.skip_newlines();
+        let mut words = None;
+        if self.peek_word("in") {
+            self.tokens.next();
+            let mut list = Vec::new();
+            while let Some(Token::Word(_)) = self.tokens.peek() {
+                list.push(self.next_word()?);
+            }
+            match self.tokens.next() {
+                Some(Token::Op(Op::Semi | Op::Newline)) => (),
+                Some(token) => return Err(ParseError::Unexpected(token)),
+                None => return Err(ParseError::UnexpectedEof),
+            }
+            words = Some(list);
+        }
+        self.skip_newlines();
+        let body = self.do_group()?;
+        Ok(For { name, words, body })
+    }
+
+    /// `do list; done`, the body of a loop
+    fn do_group(&mut self) -> Result<List, ParseError> {
+        self.expect_word("do")?;
+        let body = self.compound_body()?;
+        self.expect_word("done")?;
+        Ok(body)
+    }
+
+    fn case_clause(&mut self) -> Result<Case, ParseError> {
+        self.tokens.next();
+        let word = self.next_word()?;
+        self.skip_newlines();
+        self.expect_word("in")?;
+        let mut items = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.peek_word("esac") {
+                break;
+            }
+            if self.peek_op() == Some(Op::LParen) {
+                self.tokens.next();
+            }
+            let mut patterns = vec![self.next_word()?];
+            while self.peek_op() == Some(Op::Pipe) {
+                self.tokens.next();
+                patterns.push(self.next_word()?);
+            }
+            self.expect(Token::Op(Op::RParen))?;
+            let body = self.list()?;
+            items.push(CaseItem { patterns, body });
+            // the last item may end without `;;`
+            if self.peek_op() == Some(Op::DSemi) {
+                self.tokens.next();
+            } else {
+                break;
+            }
+        }
+        self.skip_newlines();
+        self.expect_word("esac")?;
+        Ok(Case { word, items })
+    }
+
+    /// `name() body` or `function name [()] body`, the body is a compound command
+    fn function(&mut self) -> Result<Command, ParseError> {
+        if self.peek_word("function") {
+            self.tokens.next();
+        }
+        let name = match self.tokens.next() {
+            Some(Token::Word(name)) => name,
+            _ => unreachable!(),
+        };
+        if self.peek_op() == Some(Op::LParen) {
+            self.tokens.next();
+            self.expect(Token::Op(Op::RParen))?;
+        }
+        self.skip_newlines();
+        if self.peek_op() != Some(Op::LParen) && !self.peek_word("{") {
+            return Err(match self.tokens.next() {
+                Some(token) => ParseError::Unexpected(token),
+                None => ParseError::UnexpectedEof,
+            });
+        }
+        let body = self.command()?;
+        Ok(Command::Function(name, Box::new(body)))
+    }
+
+    /// the non-empty list inside `( )` or `{ }`
+    fn compound_body(&mut self) -> Result<List, ParseError> {
+        let list = self.list()?;
+        if list.0.is_empty() {
+            return Err(match self.tokens.next() {
+                Some(token) => ParseError::Unexpected(token),
+                None => ParseError::UnexpectedEof,
+            });
+        }
+        Ok(list)
+    }
+
+    fn simple_command(&mut self) -> Result<SimpleCommand, ParseError> {
+        let mut command = SimpleCommand::default();
+        loop {
+            match self.tokens.peek() {
+                Some(Token::Word(_)) => {
+                    if let Some(Token::Word(word)) = self.tokens.next() {
+                        if command.words.is_empty() && split_assignment(&word).is_some() {
+                            command.assignments.push(word);
+                        } else {
+                            command.words.push(word);
+                        }
+                    }
+                }
+                Some(Token::IoNumber(_)) => command.redirects.push(self.redirect()?),
+                Some(Token::Op(op)) if op.is_redirect() => {
+                    command.redirects.push(self.redirect()?);
+                }
+                _ => break,
+            }
+        }
+        if command.assignments.is_empty()
+            && command.words.is_empty()
+            && command.redirects.is_empty()
+        {
+            return Err(match self.tokens.next() {
+                Some(token) => ParseError::Unexpected(token),
+                None => ParseError::UnexpectedEof,
+            });
+        }
+        Ok(command)
+    }
+
+    fn redirects(&mut self) -> Result<Vec<Redirect>, ParseError> {
+        let mut redirects = Vec::new();
+        while matches!(self.tokens.peek(), Some(Token::IoNumber(_)))
+            || self.peek_op().is_some_and(|op| op.is_redirect())
+        {
+            redirects.push(self.redirect()?);
+        }
+        Ok(redirects)
+    }
+
+    fn redirect(&mut self) -> Result<Redirect, ParseError> {
+        let fd = match self
+            .tokens
+            .next_if(|token| matches!(token, Token::IoNumber(_)))
+        {
+            Some(Token::IoNumber(fd)) => Some(fd),
+            _ => None,
+        };
+        let op = match self.tokens.next() {
+            Some(Token::Op(op)) if op.is_redirect() => op,
+            Some(token) => return Err(ParseError::Unexpected(token)),
+            None => return Err(ParseError::UnexpectedEof),
+        };
+        let target = match self.tokens.next() {
+            Some(Token::HereDoc(here_doc)) => RedirectTarget::HereDoc(here_doc),
+            Some(Token::Word(word)) => RedirectTarget::Word(word),
+            Some(token) => return Err(ParseError::Unexpected(token)),
+            None => return Err(ParseError::UnexpectedEof),
+        };
+        Ok(Redirect { fd, op, target })
+    }
+}
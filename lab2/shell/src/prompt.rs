@@ -0,0 +1,156 @@
+use crate::dirs;
+use crate::shell::Shell;
+use nix::unistd::{gethostname, getuid, User};
+use std::env;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+/// `PS1` when not set: the current directory in green, then `> `
+pub const DEFAULT_PS1: &str = "\\[\\e[38;5;10m\\]\\w\\[\\e[0m\\]> ";
+/// `PS2` when not set, shown on the following lines of an unfinished command
+pub const DEFAULT_PS2: &str = "> ";
+/// `PS4` when not set, printed before each command traced by `set -x`
+pub const DEFAULT_PS4: &str = "+ ";
+
+/// render a prompt from `PS1` or `PS2`, with escapes like bash
+///
+/// `\u` user, `\h`/`\H` short/full host name, `\w` current directory with `~`,
+/// `\W` its last component, `\t` time as HH:MM:SS, `\A` as HH:MM, `\d` date,
+/// `\?` last exit status, `\g` git branch, `\$` `#` for root and `$` otherwise,
+/// `\n` newline, `\e` escape, `\NNN` octal char, `\\` backslash. `\[` and `\]`
+/// mark colors and such, which take no room
+pub fn render(template: &str, shell: &Shell) -> String {
+    let mut prompt = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            prompt.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('u') => prompt += &user_name(shell),
+            Some('h') => prompt += host_name().split('.').next().unwrap_or_default(),
+            Some('H') => prompt += &host_name(),
+            Some('w') => prompt += &current_dir(shell),
+            Some('W') => {
+                let dir = current_dir(shell);
+                match dir.rsplit_once('/') {
+                    Some((_, name)) if !name.is_empty() => prompt += name,
+                    _ => prompt += &dir,
+                }
+            }
+            Some('t') => {
+                let tm = local_time(now());
+                prompt += &format!("{:02}:{:02}:{:02}", tm.tm_hour, tm.tm_min, tm.tm_sec);
+            }
+            Some('A') => {
+                let tm = local_time(now());
+                prompt += &format!("{:02}:{:02}", tm.tm_hour, tm.tm_min);
+            }
+            Some('d') => prompt += &date(&local_time(now())),
+            Some('?') => prompt += &shell.status.to_string(),
+            Some('g') => prompt += &git_branch().unwrap_or_default(),
+            Some('$') => prompt.push(if getuid().is_root() { '#' } else { '$' }),
+            Some('n') => prompt.push('\n'),
+            Some('e') => prompt.push('\x1b'),
+            Some('a') => prompt.push('\x07'),
+            Some('[' | ']') => (),
+            Some('\\') => prompt.push('\\'),
+            Some(c @ '0'..='7') => {
+                let mut code = c.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => {
+                            code = code * 8 + digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                prompt.extend(char::from_u32(code));
+            }
+            Some(c) => {
+                prompt.push('\\');
+                prompt.push(c);
+            }
+            None => prompt.push('\\'),
+        }
+    }
+    prompt
+}
+
+fn user_name(shell: &Shell) -> String {
+    match User::from_uid(getuid()) {
+        Ok(Some(user)) => user.name,
+        _ => shell.vars.get("USER").unwrap_or_default().to_string(),
+    }
+}
+
+fn host_name() -> String {
+    let mut buf = [0u8; 256];
+    match gethostname(&mut buf) {
+        Ok(name) => name.to_string_lossy().into_owned(),
+        Err(_) => String::new(),
+    }
+}
+
+/// the current directory, `~` for the home directory
+fn current_dir(shell: &Shell) -> String {
+    dirs::abbreviate(&dirs::current(shell), shell)
+}
+
+/// a time in seconds since the epoch, broken down in the local time zone
+pub fn local_time(time: i64) -> libc::tm {
+    unsafe {
+        let mut tm: libc::tm = mem::zeroed();
+        libc::localtime_r(&(time as libc::time_t), &mut tm);
+        tm
+    }
+}
+
+fn now() -> i64 {
+    unsafe { libc::time(std::ptr::null_mut()) as i64 }
+}
+
+/// the date like `Tue May 26`
+fn date(tm: &libc::tm) -> String {
+    const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    format!(
+        "{} {} {:02}",
+        DAYS[tm.tm_wday as usize % 7],
+        MONTHS[tm.tm_mon as usize % 12],
+        tm.tm_mday
+    )
+}
+
+/// the branch checked out in the git repository around the current directory,
+/// or the short commit id when detached
+fn git_branch() -> Option<String> {
+    let git_dir = find_git_dir(&env::current_dir().ok()?)?;
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    match head.strip_prefix("ref: ") {
+        Some(reference) => Some(reference.trim_start_matches("refs/heads/").to_string()),
+        None => Some(head.chars().take(7).collect()),
+    }
+}
+
+/// `.git` of the directory or one of its parents, following `gitdir:` files
+/// of worktrees and submodules
+fn find_git_dir(dir: &Path) -> Option<PathBuf> {
+    for dir in dir.ancestors() {
+        let git = dir.join(".git");
+        if git.is_dir() {
+            return Some(git);
+        }
+        if let Ok(text) = fs::read_to_string(&git) {
+            let path = text.trim().strip_prefix("gitdir:")?.trim();
+            return Some(dir.join(path));
+        }
+    }
+    None
+}
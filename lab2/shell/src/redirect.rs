@@ -0,0 +1,157 @@
+use nix::fcntl::{fcntl, FcntlArg};
+use nix::sys::memfd::{memfd_create, MemFdCreateFlag};
+use nix::unistd::{close, dup2};
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+
+/// the lowest fd used for the files held by a table, so that moving them to
+/// their places in the child never overwrites another one
+const FIRST_HIGH_FD: RawFd = 10;
+
+/// file descriptors a command gets from pipes and redirections, in the shell
+/// until the command is started. fds not in the table are inherited
+#[derive(Default)]
+pub struct FdTable {
+    /// (fd in the command, the file behind it or None if closed)
+    entries: Vec<(RawFd, Option<File>)>,
+}
+
+impl FdTable {
+    /// a table with the stdin and stdout suggested by pipe
+    pub fn new(stdin: Option<File>, stdout: Option<File>) -> io::Result<FdTable> {
+        let mut table = FdTable::default();
+        for (fd, file) in [(0, stdin), (1, stdout)] {
+            if let Some(file) = file {
+                table.set(fd, Some(file))?;
+            }
+        }
+        Ok(table)
+    }
+
+    /// let the fd of the command refer to the file, or close it
+    pub fn set(&mut self, fd: RawFd, file: Option<File>) -> io::Result<()> {
+        let file = match file {
+            Some(file) => Some(dup_high(file.as_raw_fd())?),
+            None => None,
+        };
+        match self.entries.iter_mut().find(|(entry, _)| *entry == fd) {
+            Some(entry) => entry.1 = file,
+            None => self.entries.push((fd, file)),
+        }
+        Ok(())
+    }
+
+    /// let `fd` refer to what `source` refers to now, like `fd>&source`
+    pub fn duplicate(&mut self, fd: RawFd, source: RawFd) -> io::Result<()> {
+        let file = match self.entries.iter().find(|(entry, _)| *entry == source) {
+            Some((_, Some(file))) => dup_high(file.as_raw_fd())?,
+            Some((_, None)) => return Err(io::Error::from_raw_os_error(libc::EBADF)),
+            None => dup_high(source)?,
+        };
+        self.set(fd, Some(file))
+    }
+
+    /// a writer for an fd of the command, to be used by built-ins in the shell
+    pub fn writer(&self, fd: RawFd) -> Box<dyn Write> {
+        match self.entries.iter().find(|(entry, _)| *entry == fd) {
+            Some((_, Some(file))) => match file.try_clone() {
+                Ok(file) => Box::new(file),
+                Err(_) => Box::new(Closed),
+            },
+            Some((_, None)) => Box::new(Closed),
+            None if fd == 2 => Box::new(io::stderr()),
+            None => Box::new(io::stdout()),
+        }
+    }
+
+    /// what the child has to do: (fd, the fd to copy to it or None to close it)
+    /// the table must live until the child has set them
+    pub fn plan(&self) -> Vec<(RawFd, Option<RawFd>)> {
+        self.entries
+            .iter()
+            .map(|(fd, file)| (*fd, file.as_ref().map(|file| file.as_raw_fd())))
+            .collect()
+    }
+}
+
+/// a writer for a closed fd, every write fails
+struct Closed;
+
+impl Write for Closed {
+    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+        Err(io::Error::from_raw_os_error(libc::EBADF))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// move the files of a plan to their fds, in the child before it runs
+/// only async-signal-safe calls here
+pub fn install(plan: &[(RawFd, Option<RawFd>)]) -> io::Result<()> {
+    for (fd, source) in plan {
+        match source {
+            Some(source) => dup2(*source, *fd).map(|_| ())?,
+            None => close(*fd).unwrap_or(()),
+        }
+    }
+    Ok(())
+}
+
+/// move the files of a plan to their fds in the shell itself, for functions and
+/// groups run by the shell. return what the fds were, to be restored after
+pub fn install_saved(plan: &[(RawFd, Option<RawFd>)]) -> io::Result<Vec<(RawFd, Option<File>)>> {
+    let mut saved = Vec::new();
+    for (fd, source) in plan {
+        saved.push((*fd, dup_high(*fd).ok()));
+        let result = match source {
+            Some(source) => dup2(*source, *fd).map(|_| ()),
+            None => close(*fd).or(Ok(())),
+        };
+        if let Err(err) = result {
+            restore(saved);
+            return Err(err.into());
+        }
+    }
+    Ok(saved)
+}
+
+/// put back the fds saved by `install_saved`
+pub fn restore(saved: Vec<(RawFd, Option<File>)>) {
+    for (fd, file) in saved.into_iter().rev() {
+        match file {
+            Some(file) => dup2(file.as_raw_fd(), fd).map(|_| ()).unwrap_or(()),
+            None => close(fd).unwrap_or(()),
+        }
+    }
+}
+
+/// duplicate an fd to a close-on-exec one out of the way of redirections
+fn dup_high(fd: RawFd) -> io::Result<File> {
+    let fd = fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(FIRST_HIGH_FD))?;
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+/// open the file for redirection, create it first if needed
+pub fn open_file(file_path: &str, read: bool, write: bool, append: bool) -> io::Result<File> {
+    OpenOptions::new()
+        .read(read)
+        .write(write && !append)
+        .append(append)
+        .create(write || append)
+        .truncate(write && !append && !read)
+        .open(file_path)
+}
+
+/// a file to read the text from, for here-documents and here-strings
+pub fn text_file(text: &str) -> io::Result<File> {
+    let name = CString::new("llysh-here-doc").unwrap();
+    let fd = memfd_create(&name, MemFdCreateFlag::MFD_CLOEXEC)?;
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    file.write_all(text.as_bytes())?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
@@ -0,0 +1,198 @@
+use crate::builtin::is_built_in;
+use crate::shell::Shell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+/// words the parser takes as reserved when they start a command
+pub const KEYWORDS: [&str; 16] = [
+    "case", "do", "done", "elif", "else", "esac", "fi", "for", "function", "if", "in", "then",
+    "until", "while", "{", "}",
+];
+
+/// `$PATH` of `command -p`, where the standard utilities are
+pub const DEFAULT_PATH: &str = "/usr/local/bin:/usr/bin:/bin:/usr/sbin:/sbin";
+
+/// what a command name refers to, looked for in this order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Kind {
+    /// with the text it is replaced with
+    Alias(String),
+    Keyword,
+    Function,
+    BuiltIn,
+    /// a program at the path, and whether it was remembered by `hash`
+    File(String, bool),
+}
+
+impl Kind {
+    /// the word `type -t` prints
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kind::Alias(_) => "alias",
+            Kind::Keyword => "keyword",
+            Kind::Function => "function",
+            Kind::BuiltIn => "builtin",
+            Kind::File(..) => "file",
+        }
+    }
+}
+
+/// what a name refers to as a command, the one used first, or all of them with
+/// every program of that name in `$PATH`. `path_only` leaves out all but programs
+pub fn resolve(name: &str, all: bool, path_only: bool, shell: &mut Shell) -> Vec<Kind> {
+    let mut kinds = Vec::new();
+    if !path_only {
+        if let Some(text) = shell.aliases.get(name) {
+            kinds.push(Kind::Alias(text.clone()));
+        }
+        if KEYWORDS.contains(&name) {
+            kinds.push(Kind::Keyword);
+        }
+        if shell.functions.contains_key(name) {
+            kinds.push(Kind::Function);
+        }
+        if is_built_in(name) {
+            kinds.push(Kind::BuiltIn);
+        }
+    }
+    let path = shell.vars.get("PATH").unwrap_or_default().to_string();
+    if all {
+        kinds.extend(
+            search(name, &path)
+                .into_iter()
+                .map(|file| Kind::File(file, false)),
+        );
+    } else if kinds.is_empty() {
+        let hashed = shell.hash.contains(name);
+        if let Some(file) = shell.hash.find(name, &path) {
+            kinds.push(Kind::File(file, hashed));
+        }
+    }
+    if !all {
+        kinds.truncate(1);
+    }
+    kinds
+}
+
+/// programs of a name in a `$PATH`, in order. a name with `/` is a path itself
+pub fn search(name: &str, path: &str) -> Vec<String> {
+    candidates(name, path)
+        .into_iter()
+        .filter(|file| is_executable(Path::new(file)))
+        .collect()
+}
+
+/// the first file of a name in a `$PATH`, whether it can be executed or not.
+/// a name with `/` is a path itself
+pub fn search_file(name: &str, path: &str) -> Option<String> {
+    if name.contains('/') {
+        return Some(name.to_string());
+    }
+    candidates(name, path)
+        .into_iter()
+        .find(|file| fs::metadata(file).is_ok_and(|meta| meta.is_file()))
+}
+
+/// the paths a name could be at in a `$PATH`
+fn candidates(name: &str, path: &str) -> Vec<String> {
+    if name.contains('/') {
+        return vec![name.to_string()];
+    }
+    path.split(':')
+        .map(|dir| match dir {
+            // an empty entry is the current directory
+            "" => format!("./{}", name),
+            _ => format!("{}/{}", dir.trim_end_matches('/'), name),
+        })
+        .collect()
+}
+
+pub fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+/// where programs were found in `$PATH`, so that it is searched once for each,
+/// listed and cleared by `hash`
+#[derive(Default)]
+pub struct CommandHash {
+    /// name -> (path, how many times it was used)
+    entries: BTreeMap<String, (String, usize)>,
+    /// the `$PATH` the entries were found in, they are forgotten when it changes
+    path: String,
+}
+
+impl CommandHash {
+    /// the program to run for a name, remembered, or searched for and then
+    /// remembered, counted as a use
+    pub fn lookup(&mut self, name: &str, path: &str) -> Option<String> {
+        let file = self.remember(name, path)?;
+        if let Some((_, hits)) = self.entries.get_mut(name) {
+            *hits += 1;
+        }
+        Some(file)
+    }
+
+    /// like `lookup`, but it does not count as a use, like `hash name`. a
+    /// remembered one no longer there is searched for again
+    pub fn remember(&mut self, name: &str, path: &str) -> Option<String> {
+        if name.contains('/') {
+            return search(name, path).pop();
+        }
+        if self.path != path {
+            self.entries.clear();
+            self.path = path.to_string();
+        }
+        if let Some(file) = self.find(name, path) {
+            let hits = self.entries.get(name).map_or(0, |(_, hits)| *hits);
+            self.entries.insert(name.to_string(), (file.clone(), hits));
+            return Some(file);
+        }
+        self.entries.remove(name);
+        None
+    }
+
+    /// the program a name would run, without remembering it, like for `type`
+    pub fn find(&self, name: &str, path: &str) -> Option<String> {
+        if name.contains('/') {
+            return search(name, path).pop();
+        }
+        let remembered = match self.path == path {
+            true => self.entries.get(name),
+            false => None,
+        };
+        match remembered {
+            Some((file, _)) if is_executable(Path::new(file)) => Some(file.clone()),
+            _ => search(name, path).into_iter().next(),
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.contains_key(name)
+    }
+
+    /// remember a path for a name, like `hash -p path name`
+    pub fn insert(&mut self, name: &str, file: &str) {
+        self.entries.insert(name.to_string(), (file.to_string(), 0));
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.entries.remove(name).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries.get(name).map(|(file, _)| file.as_str())
+    }
+
+    /// (name, path, hits) of every program remembered
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String, usize)> {
+        self.entries
+            .iter()
+            .map(|(name, (file, hits))| (name, file, *hits))
+    }
+}
@@ -0,0 +1,179 @@
+use crate::complete::Completion;
+use crate::dirs;
+use crate::history::History;
+use crate::jobs::Jobs;
+use crate::parser::Command;
+use crate::prompt;
+use crate::resolve::CommandHash;
+use crate::vars::Variables;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::os::unix::io::RawFd;
+
+/// state of a shell session, shared by the executor and built-ins
+pub struct Shell {
+    pub history: History,
+    /// exit status of the last pipeline, expanded by `$?`
+    pub status: i32,
+    /// exit status of every command of the last pipeline, expanded by `$PIPESTATUS`
+    pub pipestatus: Vec<i32>,
+    pub jobs: Jobs,
+    /// the controlling terminal when job control is on
+    pub tty: Option<RawFd>,
+    /// positional parameters, `$0` first
+    pub args: Vec<String>,
+    pub options: Options,
+    /// shell variables, the exported ones are the environment of commands
+    pub vars: Variables,
+    /// how the arguments of commands complete in the line editor
+    pub completion: Completion,
+    /// `alias` names and the text they are replaced with
+    pub aliases: BTreeMap<String, String>,
+    /// functions by name, with their bodies
+    pub functions: HashMap<String, Command>,
+    /// where programs were found in `$PATH`
+    pub hash: CommandHash,
+    /// how many function calls and sourced files are being run, `return` works
+    /// only inside one
+    pub call_depth: usize,
+    /// how many loops are being run, `break` and `continue` work only inside one
+    pub loop_depth: usize,
+    /// set by a built-in to leave the commands being run, like `return`
+    pub control: Option<Control>,
+    /// reading commands from the user rather than from a script
+    pub interactive: bool,
+    /// running in a forked child of the shell, like a stage of a pipeline
+    pub subshell: bool,
+    /// how many conditions are being run, like that of `if` or the commands
+    /// before `&&`, whose failure does not leave the shell for `set -e`
+    pub conditions: usize,
+    /// `trap` actions by signal number, 0 for `EXIT`, empty to ignore it
+    pub traps: BTreeMap<i32, String>,
+    /// directories saved by `pushd`, the last one saved first
+    pub dir_stack: Vec<String>,
+    /// the shell's ends of the pipes of process substitutions, named by
+    /// `/dev/fd/N` and kept open until the command given them is done with them
+    pub substitutions: Vec<File>,
+}
+
+/// a jump out of the commands being run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    /// leave the function or sourced file being run
+    Return,
+    /// leave n loops
+    Break(usize),
+    /// leave n - 1 loops and go on with the next iteration of the last one
+    Continue(usize),
+    /// a foreground job was interrupted by SIGINT, leave everything
+    Interrupt,
+}
+
+/// shell options that change how commands are run, set by `shopt` or `set`
+#[derive(Default)]
+pub struct Options {
+    /// a pattern matching no file is an error
+    pub failglob: bool,
+    /// a pattern matching no file is removed
+    pub nullglob: bool,
+    /// `**` matches any levels of directories
+    pub globstar: bool,
+    /// `set -e`, leave the shell when a command fails outside of a condition
+    pub errexit: bool,
+    /// `set -u`, expanding an unset parameter is an error
+    pub nounset: bool,
+    /// `set -x`, print commands after `$PS4` as they are run
+    pub xtrace: bool,
+    /// `set -n`, read commands without running them, in scripts only
+    pub noexec: bool,
+    /// `set -C`, `>` does not overwrite files, but `>|` does
+    pub noclobber: bool,
+    /// `set -o pipefail`, the status of a pipeline is that of the last command
+    /// failing in it
+    pub pipefail: bool,
+}
+
+impl Options {
+    /// names of the options of `shopt`
+    pub const NAMES: [&'static str; 3] = ["failglob", "globstar", "nullglob"];
+    /// names of the options of `set -o`, with their letters
+    pub const SET_NAMES: [(&'static str, Option<char>); 6] = [
+        ("errexit", Some('e')),
+        ("noclobber", Some('C')),
+        ("noexec", Some('n')),
+        ("nounset", Some('u')),
+        ("pipefail", None),
+        ("xtrace", Some('x')),
+    ];
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "failglob" => Some(&mut self.failglob),
+            "globstar" => Some(&mut self.globstar),
+            "nullglob" => Some(&mut self.nullglob),
+            _ => None,
+        }
+    }
+
+    /// an option of `set -o` by name
+    pub fn set_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "errexit" => Some(&mut self.errexit),
+            "noclobber" => Some(&mut self.noclobber),
+            "noexec" => Some(&mut self.noexec),
+            "nounset" => Some(&mut self.nounset),
+            "pipefail" => Some(&mut self.pipefail),
+            "xtrace" => Some(&mut self.xtrace),
+            _ => None,
+        }
+    }
+
+    /// the letters of the options of `set` that are on, expanded by `$-`
+    pub fn flags(&self) -> String {
+        let flags = [
+            (self.errexit, 'e'),
+            (self.noclobber, 'C'),
+            (self.noexec, 'n'),
+            (self.nounset, 'u'),
+            (self.xtrace, 'x'),
+        ];
+        flags
+            .iter()
+            .filter(|(on, _)| *on)
+            .map(|(_, letter)| letter)
+            .collect()
+    }
+}
+
+impl Shell {
+    pub fn new(history: History, args: Vec<String>) -> Shell {
+        let mut vars = Variables::from_env();
+        dirs::init(&mut vars);
+        if vars.get_var("PS4").is_none() {
+            vars.set("PS4", prompt::DEFAULT_PS4).ok();
+        }
+        Shell {
+            history,
+            status: 0,
+            pipestatus: vec![0],
+            jobs: Jobs::default(),
+            tty: None,
+            args,
+            options: Options::default(),
+            completion: Completion::default(),
+            vars,
+            aliases: BTreeMap::new(),
+            functions: HashMap::new(),
+            hash: CommandHash::default(),
+            call_depth: 0,
+            loop_depth: 0,
+            control: None,
+            interactive: false,
+            subshell: false,
+            conditions: 0,
+            traps: BTreeMap::new(),
+            dir_stack: Vec::new(),
+            substitutions: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use nix::fcntl::OFlag;
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::unistd::{pipe2, read};
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU8, Ordering};
+
+/// what happens when a signal arrives
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Disposition {
+    Default = 0,
+    /// queued for the shell, see `take`
+    Catch = 1,
+    Ignore = 2,
+}
+
+/// one more than the largest signal number
+const COUNT: usize = 65;
+/// no disposition set by `trap`
+const NO_TRAP: u8 = u8::MAX;
+
+/// signals caught and not taken yet
+static PENDING: [AtomicBool; COUNT] = [const { AtomicBool::new(false) }; COUNT];
+/// what the shell does with a signal for itself, as a `Disposition`
+static OWN: [AtomicU8; COUNT] = [const { AtomicU8::new(0) }; COUNT];
+/// what `trap` does with a signal instead, `NO_TRAP` if nothing
+static TRAPPED: [AtomicU8; COUNT] = [const { AtomicU8::new(NO_TRAP) }; COUNT];
+/// the pipe the handler writes to, so that a `poll` wakes up, -1 before `init`
+static PIPE_READ: AtomicI32 = AtomicI32::new(-1);
+static PIPE_WRITE: AtomicI32 = AtomicI32::new(-1);
+
+/// only async-signal-safe calls here: the signal is marked and the shell
+/// acts on it later
+extern "C" fn handle(sig: libc::c_int) {
+    unsafe {
+        // write may change errno, which the code interrupted may be reading
+        let errno = *libc::__errno_location();
+        PENDING[sig as usize].store(true, Ordering::Relaxed);
+        let byte = sig as u8;
+        let fd = PIPE_WRITE.load(Ordering::Relaxed);
+        libc::write(fd, &byte as *const u8 as *const libc::c_void, 1);
+        *libc::__errno_location() = errno;
+    }
+}
+
+/// create the pipe written by the handler, before any signal is caught
+pub fn init() {
+    if let Ok((read, write)) = pipe2(OFlag::O_CLOEXEC | OFlag::O_NONBLOCK) {
+        PIPE_READ.store(read, Ordering::Relaxed);
+        PIPE_WRITE.store(write, Ordering::Relaxed);
+    }
+}
+
+/// the end of the pipe to poll, readable once a signal is caught
+pub fn fd() -> RawFd {
+    PIPE_READ.load(Ordering::Relaxed)
+}
+
+/// set what the shell does with a signal for itself, which `trap` overrides
+pub fn set(signal: Signal, disposition: Disposition) {
+    OWN[signal as usize].store(disposition as u8, Ordering::Relaxed);
+    apply(signal);
+}
+
+/// set what `trap` does with a signal, None to go back to that of the shell
+pub fn set_trap(signal: Signal, disposition: Option<Disposition>) {
+    let value = disposition.map_or(NO_TRAP, |disposition| disposition as u8);
+    TRAPPED[signal as usize].store(value, Ordering::Relaxed);
+    apply(signal);
+}
+
+fn apply(signal: Signal) {
+    let handler = match effective(signal as usize) {
+        1 => SigHandler::Handler(handle),
+        2 => SigHandler::SigIgn,
+        _ => SigHandler::SigDfl,
+    };
+    let action = SigAction::new(handler, SaFlags::SA_RESTART, SigSet::empty());
+    unsafe { sigaction(signal, &action) }.ok();
+}
+
+/// the disposition in effect for a signal number, as a `Disposition`
+fn effective(sig: usize) -> u8 {
+    match TRAPPED[sig].load(Ordering::Relaxed) {
+        NO_TRAP => OWN[sig].load(Ordering::Relaxed),
+        trapped => trapped,
+    }
+}
+
+/// empty the pipe, the signals caught stay pending
+pub fn drain() {
+    let mut buf = [0; 64];
+    while let Ok(1..) = read(fd(), &mut buf) {}
+}
+
+/// whether a signal was caught since it was last taken
+pub fn take_signal(signal: Signal) -> bool {
+    PENDING[signal as usize].swap(false, Ordering::Relaxed)
+}
+
+/// the signals caught since they were last taken, but SIGCHLD, which is left
+/// for reaping jobs before the prompt
+pub fn take() -> Vec<Signal> {
+    drain();
+    Signal::iterator()
+        .filter(|&signal| signal != Signal::SIGCHLD && take_signal(signal))
+        .collect()
+}
+
+/// in a new child, set the signals the shell catches or ignores back to the
+/// default, but those ignored by `trap`. only async-signal-safe calls here
+pub fn reset_child() {
+    for signal in Signal::iterator() {
+        let sig = signal as usize;
+        let trapped = TRAPPED[sig].load(Ordering::Relaxed);
+        if trapped != Disposition::Ignore as u8 && effective(sig) != 0 {
+            unsafe { libc::signal(sig as libc::c_int, libc::SIG_DFL) };
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::BTreeMap;
+use std::env;
+
+/// a shell variable
+#[derive(Debug, Clone, Default)]
+pub struct Variable {
+    /// None for a variable only declared, like by `export NAME`
+    pub value: Option<String>,
+    /// passed to the environment of commands
+    pub exported: bool,
+    /// can not be changed or unset
+    pub readonly: bool,
+}
+
+/// shell variables, separate from the environment of the shell process
+#[derive(Debug, Clone, Default)]
+pub struct Variables {
+    vars: BTreeMap<String, Variable>,
+    /// for each function being run, the variables it made local and what they were
+    frames: Vec<Vec<(String, Option<Variable>)>>,
+}
+
+/// whether a name can be a variable name
+pub fn is_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// split `NAME=value`, if NAME is a valid name
+pub fn split_assignment(word: &str) -> Option<(&str, &str)> {
+    let (name, value) = word.split_once('=')?;
+    is_name(name).then_some((name, value))
+}
+
+/// quote a value so that it reads back as itself, for listings
+pub fn quote(value: &str) -> String {
+    if !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./:,+@%=".contains(c))
+    {
+        return value.to_string();
+    }
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+impl Variables {
+    /// variables of the environment the shell is started with, all exported
+    /// a name that is not UTF-8 is left out, and a value is read lossily
+    pub fn from_env() -> Variables {
+        let vars = env::vars_os()
+            .filter_map(|(name, value)| {
+                let var = Variable {
+                    value: Some(value.to_string_lossy().into_owned()),
+                    exported: true,
+                    readonly: false,
+                };
+                Some((name.into_string().ok()?, var))
+            })
+            .collect();
+        Variables {
+            vars,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.vars.get(name)?.value.as_deref()
+    }
+
+    pub fn get_var(&self, name: &str) -> Option<&Variable> {
+        self.vars.get(name)
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let var = self.vars.entry(name.to_string()).or_default();
+        if var.readonly {
+            return Err(format!("{}: readonly variable", name));
+        }
+        var.value = Some(value.to_string());
+        Ok(())
+    }
+
+    /// mark a variable exported or not, declare it if it does not exist
+    pub fn export(&mut self, name: &str, exported: bool) {
+        self.vars.entry(name.to_string()).or_default().exported = exported;
+    }
+
+    /// make a variable readonly, declare it if it does not exist
+    pub fn set_readonly(&mut self, name: &str) {
+        self.vars.entry(name.to_string()).or_default().readonly = true;
+    }
+
+    /// put a variable back as it was, like after a temporary assignment
+    pub fn restore(&mut self, name: &str, var: Option<Variable>) {
+        match var {
+            Some(var) => self.vars.insert(name.to_string(), var),
+            None => self.vars.remove(name),
+        };
+    }
+
+    /// start the scope of a function call
+    pub fn push_frame(&mut self) {
+        self.frames.push(Vec::new());
+    }
+
+    /// end the scope of a function call, restore the variables made local in it
+    pub fn pop_frame(&mut self) {
+        if let Some(frame) = self.frames.pop() {
+            for (name, var) in frame.into_iter().rev() {
+                self.restore(&name, var);
+            }
+        }
+    }
+
+    /// make a variable local to the function being run, with an empty value
+    pub fn local(&mut self, name: &str) -> Result<(), String> {
+        let frame = match self.frames.last_mut() {
+            Some(frame) => frame,
+            None => return Err("can only be used in a function".to_string()),
+        };
+        let var = self.vars.get(name);
+        if var.is_some_and(|var| var.readonly) {
+            return Err(format!("{}: readonly variable", name));
+        }
+        if !frame.iter().any(|(local, _)| local == name) {
+            frame.push((name.to_string(), var.cloned()));
+        }
+        self.vars.insert(name.to_string(), Variable::default());
+        Ok(())
+    }
+
+    pub fn unset(&mut self, name: &str) -> Result<(), String> {
+        match self.vars.get(name) {
+            Some(var) if var.readonly => Err(format!("{}: cannot unset: readonly variable", name)),
+            _ => {
+                self.vars.remove(name);
+                Ok(())
+            }
+        }
+    }
+
+    /// all variables, sorted by name
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Variable)> {
+        self.vars.iter()
+    }
+
+    /// name and value of exported variables with a value, the environment of commands
+    pub fn exported(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.vars
+            .iter()
+            .filter(|(_, var)| var.exported)
+            .filter_map(|(name, var)| Some((name, var.value.as_ref()?)))
+    }
+}